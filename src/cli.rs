@@ -3,20 +3,32 @@
  *
  * SPDX-License-Identifier: BSD-3-Clause
  */
+use chrono::Duration;
 use clap::Parser;
 use std::path::PathBuf;
 
+use crate::graph::GraphFormat;
 use crate::input::*;
+use crate::inspect::ReportFormat;
 
 /// biscuit manipulation program. It lets you create, attenuate, inspect biscuits, inspect snapshots and manage keypairs.
 ///
 /// Common tasks:
 /// - `biscuit keypair` to generate a new keypair
 /// - `biscuit generate --private-key-file PRIVKEY_FILE` to create a biscuit token
-/// - `biscuit attenuate FILE` to append a block to a biscuit token
+/// - `biscuit attenuate FILE` to append blocks to a biscuit token
 /// - `biscuit inspect FILE` to inspect a biscuit token
 /// - `biscuit inspect --public-key PUBKEY BISCUIT_FILE` to verify a biscuit signature
 /// - `biscuit inspect-snapshot SNAPSHOT_FILE` to inspect a biscuit snapshot
+/// - `biscuit check-attenuation --public-key PUBKEY --corpus DIR ORIGINAL_FILE ATTENUATED_FILE` to check that an attenuation only narrows rights
+/// - `biscuit key info KEY_FILE` to display the algorithm, kind and fingerprint of a key
+/// - `biscuit key convert --key-output-format pem KEY_FILE` to convert a private or public key to another format
+/// - `biscuit template describe TEMPLATE_FILE` to list the inputs expected by a token template
+/// - `biscuit optimize <FILE>` to get suggestions on making a token smaller
+/// - `biscuit inspect-request --biscuit-file BISCUIT_FILE REQUEST_FILE` to check a third-party block request before signing it
+/// - `biscuit inspect-third-party-block --biscuit-file BISCUIT_FILE BLOCK_FILE` to review a third-party block before appending it
+/// - `biscuit third-party-sign-server --private-key-file KEY_FILE SPOOL_DIR` to sign third-party blocks requested through a spool directory
+/// - `biscuit attest --private-key-file KEY_FILE --block DATALOG BISCUIT_FILE` to append a third-party block in one step
 #[derive(Parser)]
 #[clap(version, author, verbatim_doc_comment)]
 pub struct Opts {
@@ -36,6 +48,14 @@ pub enum SubCommand {
     GenerateThirdPartyBlock(GenerateThirdPartyBlock),
     AppendThirdPartyBlock(AppendThirdPartyBlock),
     Seal(Seal),
+    CheckAttenuation(Box<CheckAttenuation>),
+    Template(TemplateCmd),
+    Key(KeyCmd),
+    Optimize(Optimize),
+    InspectRequest(InspectRequest),
+    InspectThirdPartyBlock(InspectThirdPartyBlock),
+    ThirdPartySignServer(ThirdPartySignServer),
+    Attest(Attest),
 }
 
 /// Create and manipulate key pairs
@@ -45,7 +65,7 @@ pub struct KeyPairCmd {
     /// Generate the keypair from the given private key. If omitted, a random keypair will be generated
     #[clap(long, value_name("PRIVATE_KEY"), conflicts_with("from-file"))]
     pub from_private_key: Option<String>,
-    /// Generate the keypair from a private key stored in the given file (or use `-` to read it from stdin). If omitted, a random keypair will be generated
+    /// Generate the keypair from a private key stored in the given file (or use `-` to read it from stdin, `env:VAR` to read it from an environment variable, or `fd:N` to read it from a file descriptor). If omitted, a random keypair will be generated
     #[clap(long, value_name("PRIVATE_KEY_FILE"))]
     pub from_file: Option<PathBuf>,
     /// Input format for the private key (when provided).
@@ -95,14 +115,25 @@ pub struct Generate {
     /// Read the authority block from the given datalog file (or use `-` to read from stdin). If omitted, an interactive $EDITOR will be opened.
     #[clap(parse(from_os_str), value_name("DATALOG_FILE"))]
     pub authority_file: Option<PathBuf>,
+    /// Generate the biscuit from a token template. The template provides the authority block and its parameters, along with defaults for the TTL, root key id and context
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name("TEMPLATE_FILE"),
+        conflicts_with("authority-file")
+    )]
+    pub template: Option<PathBuf>,
     /// Provide a root key id, as a hint for public key selection
     #[clap(long)]
     pub root_key_id: Option<u32>,
     #[clap(flatten)]
     pub param_arg: common_args::ParamArg,
     /// Output the biscuit raw bytes directly, with no base64 encoding
-    #[clap(long)]
+    #[clap(long, conflicts_with("armored"))]
     pub raw: bool,
+    /// Output the biscuit in a PEM-style armor (`-----BEGIN BISCUIT TOKEN-----`), with the root key id as header
+    #[clap(long)]
+    pub armored: bool,
     #[clap(flatten)]
     pub private_key_args: common_args::PrivateKeyArgs,
     /// The optional context string attached to the authority block
@@ -120,17 +151,25 @@ pub struct Generate {
     pub add_ttl: Option<Ttl>,
 }
 
-/// Attenuate an existing biscuit by adding a new block
+/// Attenuate an existing biscuit by adding new blocks
 #[derive(Parser)]
 #[clap(display_order(2))]
 pub struct Attenuate {
     #[clap(flatten)]
     pub biscuit_input_args: common_args::BiscuitInputArgs,
     /// Output the biscuit raw bytes directly, with no base64 encoding
-    #[clap(long)]
+    #[clap(long, conflicts_with("armored-output"))]
     pub raw_output: bool,
+    /// Output the biscuit in a PEM-style armor, with the root key id as header
+    #[clap(long)]
+    pub armored_output: bool,
     #[clap(flatten)]
-    pub block_args: common_args::BlockArgs,
+    pub blocks_args: common_args::BlocksArgs,
+    #[clap(flatten)]
+    pub restriction_args: common_args::RestrictionArgs,
+    /// Seal the token after appending the blocks, so that it cannot be attenuated any further
+    #[clap(long)]
+    pub seal: bool,
     #[clap(flatten)]
     pub param_arg: common_args::ParamArg,
 }
@@ -142,12 +181,27 @@ pub struct Inspect {
     /// Output the results in a machine-readable format
     #[clap(long)]
     pub json: bool,
+    /// Report the serialized size of the token, per block and per component
+    #[clap(long)]
+    pub size: bool,
+    /// Only output a graph of the token blocks, their trust scopes, and the dependencies between predicates, checks and policies (the authorizer is included when provided)
+    #[clap(long, value_enum, value_name("FORMAT"), conflicts_with("json"))]
+    pub graph: Option<GraphFormat>,
+    /// Explain, for each block and for the authorizer, which blocks facts are trusted from, and which external keys the authorizer trusts
+    #[clap(long)]
+    pub trust_scopes: bool,
+    /// Write a report of the inspection to the given file
+    #[clap(long, parse(from_os_str), value_name("FILE"))]
+    pub report: Option<PathBuf>,
+    /// Format of the report written with `--report`
+    #[clap(long, value_enum, default_value_t, requires("report"))]
+    pub report_format: ReportFormat,
     #[clap(flatten)]
     pub biscuit_input_args: common_args::BiscuitInputArgs,
     /// Check the biscuit public key
     #[clap(long, conflicts_with("public-key-file"))]
     pub public_key: Option<String>,
-    /// Check the biscuit public key
+    /// Check the biscuit public key, read from the given file (or use `env:VAR` to read it from an environment variable, or `fd:N` to read it from a file descriptor)
     #[clap(long, conflicts_with("public-key"), parse(from_os_str))]
     pub public_key_file: Option<PathBuf>,
     /// Input format for the public key. raw is only available when reading the public key from a file
@@ -156,6 +210,19 @@ pub struct Inspect {
     /// Specify the private key algorithm, only when reading the private key raw bytes
     #[clap(long, value_enum, requires("public-key-file"))]
     pub public_key_algorithm: Option<Algorithm>,
+    /// Fail if the block at the given index is not a third-party block signed with the given public key. Can be repeated. Requires the root public key
+    ///
+    /// [example: 1=ed25519/07955ee5c68dbcf614142ddcecea962b91cd69d477f0ad0f8a15f28eba264f67]
+    #[clap(
+        long,
+        value_parser = clap::builder::ValueParser::new(parse_expected_external_key),
+        value_name("INDEX=PUBKEY"),
+        verbatim_doc_comment
+    )]
+    pub expect_external_key: Vec<ExpectedExternalKey>,
+    /// Fail if a third-party block is signed with a public key which is not listed in the given file (one public key per line, `#` starts a comment). Requires the root public key
+    #[clap(long, parse(from_os_str), value_name("KEYS_FILE"))]
+    pub trusted_external_keys: Option<PathBuf>,
     #[clap(flatten)]
     pub run_limits_args: common_args::RunLimitArgs,
     #[clap(flatten)]
@@ -171,8 +238,15 @@ pub struct Inspect {
     #[clap(long, parse(from_os_str), value_name("SNAPSHOT_FILE"))]
     pub dump_snapshot_to: Option<PathBuf>,
     /// Output the snapshot raw bytes directly, with no base64 encoding
-    #[clap(long, requires("dump-snapshot-to"))]
+    #[clap(
+        long,
+        requires("dump-snapshot-to"),
+        conflicts_with("dump-armored-snapshot")
+    )]
     pub dump_raw_snapshot: bool,
+    /// Output the snapshot in a PEM-style armor
+    #[clap(long, requires("dump-snapshot-to"))]
+    pub dump_armored_snapshot: bool,
     /// Save a policies snapshot to a file
     ///
     /// This snapshot will only contain the authorizer rules, before the biscuit token is loaded, and before authorization is ran.
@@ -180,8 +254,15 @@ pub struct Inspect {
     #[clap(long, parse(from_os_str), value_name("SNAPSHOT_FILE"))]
     pub dump_policies_snapshot_to: Option<PathBuf>,
     /// Output the policies snapshot raw bytes directly, with no base64 encoding
-    #[clap(long, requires("dump-snapshot-to"))]
+    #[clap(
+        long,
+        requires("dump-snapshot-to"),
+        conflicts_with("dump-armored-policies-snapshot")
+    )]
     pub dump_raw_policies_snapshot: bool,
+    /// Output the policies snapshot in a PEM-style armor
+    #[clap(long, requires("dump-policies-snapshot-to"))]
+    pub dump_armored_policies_snapshot: bool,
 }
 
 /// Inspect a snapshot, optionally query it
@@ -191,12 +272,15 @@ pub struct InspectSnapshot {
     /// Output the results in a machine-readable format
     #[clap(long)]
     pub json: bool,
-    /// Read the snapshot from the given file (or use `-` to read from stdin)
+    /// Read the snapshot from the given file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
     #[clap(parse(from_os_str))]
     pub snapshot_file: PathBuf,
     /// Read the snapshot raw bytes directly, with no base64 parsing
-    #[clap(long)]
+    #[clap(long, conflicts_with("base64-input"))]
     pub raw_input: bool,
+    /// Only accept a base64-encoded snapshot. By default, the encoding is detected automatically
+    #[clap(long)]
+    pub base64_input: bool,
     #[clap(flatten)]
     pub run_limits_args: common_args::RunLimitArgs,
     #[clap(flatten)]
@@ -212,29 +296,36 @@ pub struct GenerateThirdPartyBlockRequest {
     #[clap(flatten)]
     pub biscuit_input_args: common_args::BiscuitInputArgs,
     /// Output the request raw bytes directly, with no base64 encoding
-    #[clap(long)]
+    #[clap(long, conflicts_with("armored-output"))]
     pub raw_output: bool,
+    /// Output the request in a PEM-style armor
+    #[clap(long)]
+    pub armored_output: bool,
 }
 
 /// Generate a third-party block from a third-party block request
 #[derive(Parser)]
 #[clap(display_order(6))]
 pub struct GenerateThirdPartyBlock {
-    /// Read the request from the given file (or use `-` to read from stdin)
+    /// Read the request from the given file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
     #[clap(parse(from_os_str))]
     pub request_file: PathBuf,
     /// Read the request raw bytes directly, with no base64 parsing
-    #[clap(long)]
+    #[clap(long, conflicts_with("base64-input"))]
     pub raw_input: bool,
+    /// Only accept a base64-encoded request. By default, the encoding is detected automatically
+    #[clap(long)]
+    pub base64_input: bool,
     #[clap(flatten)]
     pub private_key_args: common_args::PrivateKeyArgs,
     /// Output the block raw bytes directly, with no base64 encoding
-    #[clap(long)]
+    #[clap(long, conflicts_with("armored-output"))]
     pub raw_output: bool,
+    /// Output the block in a PEM-style armor
+    #[clap(long)]
+    pub armored_output: bool,
     #[clap(flatten)]
-    pub block_args: common_args::BlockArgs,
-    #[clap(flatten)]
-    pub param_arg: common_args::ParamArg,
+    pub third_party_block_args: common_args::ThirdPartyBlockArgs,
 }
 
 /// Append a third-party block to a biscuit
@@ -244,12 +335,15 @@ pub struct AppendThirdPartyBlock {
     #[clap(flatten)]
     pub biscuit_input_args: common_args::BiscuitInputArgs,
     /// Output the biscuit raw bytes directly, with no base64 encoding
-    #[clap(long)]
+    #[clap(long, conflicts_with("armored-output"))]
     pub raw_output: bool,
+    /// Output the biscuit in a PEM-style armor, with the root key id as header
+    #[clap(long)]
+    pub armored_output: bool,
     /// The third-party block to append to the token.
     #[clap(long)]
     pub block_contents: Option<String>,
-    /// The third-party block to append to the token
+    /// The third-party block to append to the token, read from the given file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
     #[clap(
         long,
         parse(from_os_str),
@@ -258,8 +352,20 @@ pub struct AppendThirdPartyBlock {
     )]
     pub block_contents_file: Option<PathBuf>,
     /// Read the third-party block contents raw bytes directly, with no base64 parsing
-    #[clap(long, requires("block-contents-file"))]
+    #[clap(
+        long,
+        requires("block-contents-file"),
+        conflicts_with("block-contents"),
+        conflicts_with("base64-block-contents")
+    )]
     pub raw_block_contents: bool,
+    /// Only accept base64-encoded third-party block contents. By default, the encoding is detected automatically
+    #[clap(
+        long,
+        requires("block-contents-file"),
+        conflicts_with("block-contents")
+    )]
+    pub base64_block_contents: bool,
 }
 
 /// Seal a token, preventing further attenuation
@@ -269,15 +375,304 @@ pub struct Seal {
     #[clap(flatten)]
     pub biscuit_input_args: common_args::BiscuitInputArgs,
     /// Output the biscuit raw bytes directly, with no base64 encoding
+    #[clap(long, conflicts_with("armored-output"))]
+    pub raw_output: bool,
+    /// Output the biscuit in a PEM-style armor, with the root key id as header
+    #[clap(long)]
+    pub armored_output: bool,
+}
+
+/// Check that an attenuated biscuit is only authorized in cases where the original biscuit is authorized.
+///
+/// Both biscuits are authorized against each authorizer of a corpus of representative requests, and
+/// the cases where the attenuated biscuit is authorized but the original biscuit is not are reported
+/// as counterexamples.
+#[derive(Parser)]
+#[clap(display_order(9))]
+pub struct CheckAttenuation {
+    /// Output the results in a machine-readable format
+    #[clap(long)]
+    pub json: bool,
+    /// Read the original biscuit from the given file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
+    #[clap(parse(from_os_str))]
+    pub original_file: PathBuf,
+    /// Read the attenuated biscuit from the given file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
+    #[clap(parse(from_os_str))]
+    pub attenuated_file: PathBuf,
+    /// Read the biscuits raw bytes directly, with no base64 parsing
+    #[clap(long, conflicts_with("base64-input"))]
+    pub raw_input: bool,
+    /// Only accept base64-encoded biscuits. By default, the encoding is detected automatically
+    #[clap(long)]
+    pub base64_input: bool,
+    /// The root public key
+    #[clap(long, required_unless_present("public-key-file"))]
+    pub public_key: Option<String>,
+    /// The root public key, read from the given file (or use `env:VAR` to read it from an environment variable, or `fd:N` to read it from a file descriptor)
+    #[clap(long, conflicts_with("public-key"), parse(from_os_str))]
+    pub public_key_file: Option<PathBuf>,
+    /// Input format for the public key. raw is only available when reading the public key from a file
+    #[clap(long, value_enum, default_value_t)]
+    pub public_key_format: KeyFormat,
+    /// Specify the public key algorithm, only when reading the public key raw bytes
+    #[clap(long, value_enum, requires("public-key-file"))]
+    pub public_key_algorithm: Option<Algorithm>,
+    /// The authorizers to run: a datalog file, or a directory containing `.datalog` files
+    #[clap(long, parse(from_os_str), value_name("CORPUS"))]
+    pub corpus: PathBuf,
+    /// Include the current time in the authorizer facts
+    #[clap(long)]
+    pub include_time: bool,
+    #[clap(flatten)]
+    pub run_limits_args: common_args::RunLimitArgs,
+    #[clap(flatten)]
+    pub param_arg: common_args::ParamArg,
+}
+
+/// Work with token templates
+#[derive(Parser)]
+pub struct TemplateCmd {
+    #[clap(subcommand)]
+    pub subcmd: TemplateSubCommand,
+}
+
+#[derive(Parser)]
+pub enum TemplateSubCommand {
+    Describe(TemplateDescribe),
+}
+
+/// List the inputs expected by a token template
+#[derive(Parser)]
+pub struct TemplateDescribe {
+    /// The template file
+    #[clap(parse(from_os_str), value_name("TEMPLATE_FILE"))]
+    pub template_file: PathBuf,
+}
+
+/// Convert and describe private and public keys
+#[derive(Parser)]
+pub struct KeyCmd {
+    #[clap(subcommand)]
+    pub subcmd: KeySubCommand,
+}
+
+#[derive(Parser)]
+pub enum KeySubCommand {
+    Convert(KeyConvert),
+    Info(KeyInfo),
+}
+
+/// Convert a private or public key to another format
+#[derive(Parser)]
+pub struct KeyConvert {
+    #[clap(flatten)]
+    pub key_args: common_args::AnyKeyArgs,
+    /// Key output format
+    #[clap(long, value_enum, default_value_t)]
+    pub key_output_format: KeyFormat,
+    /// Output the public key matching a private key
+    #[clap(long)]
+    pub public: bool,
+}
+
+/// Display the algorithm, format, kind and fingerprint of a private or public key
+#[derive(Parser)]
+pub struct KeyInfo {
+    #[clap(flatten)]
+    pub key_args: common_args::AnyKeyArgs,
+}
+
+/// Suggest ways to make the datalog of a biscuit smaller.
+///
+/// Attenuation blocks cannot be rewritten, so only suggestions are displayed for an existing token.
+/// With `--authority-file`, an equivalent, smaller, authority block is written to stdout and the
+/// suggestions are written to stderr.
+#[derive(Parser)]
+#[clap(display_order(10))]
+pub struct Optimize {
+    /// Output the results in a machine-readable format
+    #[clap(long)]
+    pub json: bool,
+    /// Read the biscuit from the given file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
+    #[clap(
+        parse(from_os_str),
+        required_unless_present("authority-file"),
+        conflicts_with("authority-file")
+    )]
+    pub biscuit_file: Option<PathBuf>,
+    /// Read the biscuit raw bytes directly, with no base64 parsing
+    #[clap(long, conflicts_with("base64-input"))]
+    pub raw_input: bool,
+    /// Only accept a base64-encoded biscuit. By default, the encoding is detected automatically
+    #[clap(long)]
+    pub base64_input: bool,
+    /// Optimize the authority block datalog read from the given file (or use `-` to read from stdin)
+    #[clap(long, parse(from_os_str), value_name("DATALOG_FILE"))]
+    pub authority_file: Option<PathBuf>,
+}
+
+/// Inspect a third-party block request, and optionally check that it was generated from a given biscuit
+///
+/// A request identifies the token it was generated from by the signature of its last block, which is
+/// also the revocation id of that block.
+#[derive(Parser)]
+#[clap(display_order(11))]
+pub struct InspectRequest {
+    /// Output the results in a machine-readable format
+    #[clap(long)]
+    pub json: bool,
+    /// Read the request from the given file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
+    #[clap(parse(from_os_str))]
+    pub request_file: PathBuf,
+    /// Read the request raw bytes directly, with no base64 parsing
+    #[clap(long, conflicts_with("base64-input"))]
+    pub raw_input: bool,
+    /// Only accept a base64-encoded request. By default, the encoding is detected automatically
     #[clap(long)]
+    pub base64_input: bool,
+    /// Check that the request was generated from the given biscuit, read from a file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
+    #[clap(long, parse(from_os_str), value_name("BISCUIT_FILE"))]
+    pub biscuit_file: Option<PathBuf>,
+    /// Read the biscuit raw bytes directly, with no base64 parsing
+    #[clap(long, requires("biscuit-file"), conflicts_with("base64-biscuit"))]
+    pub raw_biscuit: bool,
+    /// Only accept a base64-encoded biscuit. By default, the encoding is detected automatically
+    #[clap(long, requires("biscuit-file"))]
+    pub base64_biscuit: bool,
+}
+
+/// Inspect a third-party block before appending it, and optionally check its signature against the biscuit it will be appended to
+#[derive(Parser)]
+#[clap(display_order(12))]
+pub struct InspectThirdPartyBlock {
+    /// Output the results in a machine-readable format
+    #[clap(long)]
+    pub json: bool,
+    /// Read the third-party block from the given file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
+    #[clap(parse(from_os_str))]
+    pub block_file: PathBuf,
+    /// Read the third-party block raw bytes directly, with no base64 parsing
+    #[clap(long, conflicts_with("base64-input"))]
+    pub raw_input: bool,
+    /// Only accept a base64-encoded third-party block. By default, the encoding is detected automatically
+    #[clap(long)]
+    pub base64_input: bool,
+    /// Check that the block was signed for the given third-party block request, read from a file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name("REQUEST_FILE"),
+        conflicts_with("biscuit-file")
+    )]
+    pub request_file: Option<PathBuf>,
+    /// Read the request raw bytes directly, with no base64 parsing
+    #[clap(long, requires("request-file"), conflicts_with("base64-request"))]
+    pub raw_request: bool,
+    /// Only accept a base64-encoded request. By default, the encoding is detected automatically
+    #[clap(long, requires("request-file"))]
+    pub base64_request: bool,
+    /// Check that the block can be appended to the given biscuit, read from a file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
+    #[clap(long, parse(from_os_str), value_name("BISCUIT_FILE"))]
+    pub biscuit_file: Option<PathBuf>,
+    /// Read the biscuit raw bytes directly, with no base64 parsing
+    #[clap(long, requires("biscuit-file"), conflicts_with("base64-biscuit"))]
+    pub raw_biscuit: bool,
+    /// Only accept a base64-encoded biscuit. By default, the encoding is detected automatically
+    #[clap(long, requires("biscuit-file"))]
+    pub base64_biscuit: bool,
+    /// Check the block signature by appending it to the biscuit, verified with the given root public key
+    #[clap(long, requires("biscuit-file"), conflicts_with("public-key-file"))]
+    pub public_key: Option<String>,
+    /// Check the block signature by appending it to the biscuit, verified with the root public key read from the given file (or use `env:VAR` to read it from an environment variable, or `fd:N` to read it from a file descriptor)
+    #[clap(
+        long,
+        parse(from_os_str),
+        requires("biscuit-file"),
+        conflicts_with("public-key")
+    )]
+    pub public_key_file: Option<PathBuf>,
+    /// Input format for the public key. raw is only available when reading the public key from a file
+    #[clap(long, value_enum, default_value_t)]
+    pub public_key_format: KeyFormat,
+    /// Specify the public key algorithm, only when reading the public key raw bytes
+    #[clap(long, value_enum, requires("public-key-file"))]
+    pub public_key_algorithm: Option<Algorithm>,
+}
+
+/// Sign third-party blocks from a spool directory, as an external attestation service would
+///
+/// A job is made of a `NAME.datalog` file, containing the datalog of the block to sign, and a `NAME.request`
+/// file, containing the third-party block request. The datalog file has to be written first: the job is
+/// processed as soon as the request file appears. The base64-encoded block is then written to `NAME.block`
+/// (or the reason why it was not signed to `NAME.error`), and the job files are removed.
+#[derive(Parser)]
+#[clap(display_order(13))]
+pub struct ThirdPartySignServer {
+    /// The directory where jobs are read from, and blocks written to. Each job is a `NAME.datalog` file with the block datalog, then a `NAME.request` file with the request. Only `.request` files are read, so requests must be written under another name (eg `NAME.request.tmp`), then renamed
+    #[clap(parse(from_os_str))]
+    pub spool_dir: PathBuf,
+    #[clap(flatten)]
+    pub private_key_args: common_args::PrivateKeyArgs,
+    /// Only sign the blocks allowed by the signing policy read from the given TOML file
+    #[clap(long, parse(from_os_str), value_name("POLICY_FILE"))]
+    pub policy: Option<PathBuf>,
+    /// Add a TTL check to each signed block. You can either provide an expiration timestamp or a duration, counted from the signing time
+    ///
+    /// [examples: 2025-04-01T00:00:00Z, 1d, 15m]
+    #[clap(
+        long,
+        parse(try_from_str = parse_ttl),
+        value_name("TTL"),
+        verbatim_doc_comment
+    )]
+    pub add_ttl: Option<Ttl>,
+    /// Process the pending jobs and exit, instead of watching the spool directory
+    #[clap(long)]
+    pub once: bool,
+    /// How often the spool directory is checked for new jobs
+    #[clap(
+        long,
+        parse(try_from_str = parse_duration),
+        value_name("DURATION"),
+        default_value("1s")
+    )]
+    pub poll_interval: Duration,
+}
+
+/// Append a third-party block to a biscuit, performing the whole third-party exchange locally: generating the request, signing the block with the third-party private key, and appending it
+#[derive(Parser)]
+#[clap(display_order(14))]
+pub struct Attest {
+    #[clap(flatten)]
+    pub biscuit_input_args: common_args::BiscuitInputArgs,
+    #[clap(flatten)]
+    pub private_key_args: common_args::PrivateKeyArgs,
+    /// Output the biscuit raw bytes directly, with no base64 encoding
+    #[clap(long, conflicts_with("armored-output"))]
     pub raw_output: bool,
+    /// Output the biscuit in a PEM-style armor, with the root key id as header
+    #[clap(long)]
+    pub armored_output: bool,
+    /// Display the block that would be appended, instead of the extended biscuit
+    #[clap(long, conflicts_with("raw-output"), conflicts_with("armored-output"))]
+    pub dry_run: bool,
+    #[clap(flatten)]
+    pub third_party_block_args: common_args::ThirdPartyBlockArgs,
 }
 
 mod common_args {
     use crate::input::*;
-    use biscuit_auth::builder::Rule;
+    use crate::keys::{read_any_key, AnyKey, KeyKind};
+    use crate::policy::read_signing_policy;
+    use biscuit_auth::{
+        builder::{BlockBuilder, Rule},
+        builder_ext::BuilderExt,
+        datalog::RunLimits,
+        PrivateKey,
+    };
     use chrono::Duration;
-    use clap::Parser;
+    use clap::{ArgMatches, Parser};
+    use std::net::IpAddr;
     use std::path::PathBuf;
 
     /// Arguments related to queries
@@ -301,12 +696,13 @@ mod common_args {
         /// Provide a value for a datalog parameter.
         ///
         /// `type` is optional and defaults to `string`.
-        /// Possible types are pubkey, string, integer, date, bytes or bool.
+        /// Possible types are pubkey, string, integer, date, bytes, bool, set or set<type>.
         /// Bytes values must be hex-encoded and start with `hex:`.
         /// Public keys must be hex-encoded and start with `ed25519/` or `secp256r1/`.
         /// Dates must be RFC3339 timestamps
+        /// Set elements are separated by commas, and are strings unless a type is provided
         ///
-        /// [examples: name=john, age:integer=42, is_happy:bool=true]
+        /// [examples: name=john, age:integer=42, is_happy:bool=true, ops:set=read,write, ids:set<integer>=1,2]
         #[clap(
         long,
         value_parser = clap::builder::ValueParser::new(parse_param),
@@ -314,6 +710,36 @@ mod common_args {
         value_name = "key[:type]=value",
     )]
         pub param: Vec<Param>,
+        /// Read datalog parameters from a JSON or TOML file, containing a map from parameter names to values.
+        ///
+        /// Strings, integers, booleans, TOML dates and arrays (as sets) are supported as values.
+        /// Other types can be provided as strings with a type annotation on the name, as with `--param`.
+        /// For arrays, the annotation can be either the element type or `set<type>`.
+        /// Values provided with `--param` take precedence over values from the file.
+        ///
+        /// [example: {"name": "john", "age": 42, "expiration:date": "2025-04-01T00:00:00Z"}]
+        #[clap(
+            long,
+            parse(from_os_str),
+            verbatim_doc_comment,
+            value_name("PARAMS_FILE")
+        )]
+        pub params_file: Option<PathBuf>,
+        /// Fail when a provided datalog parameter is not used, instead of printing a warning
+        #[clap(long)]
+        pub strict_params: bool,
+    }
+
+    impl ParamArg {
+        /// Params from `--params-file` followed by params from `--param`, so that the latter take precedence
+        pub fn params(&self) -> anyhow::Result<Vec<Param>> {
+            let mut params = match &self.params_file {
+                Some(path) => read_params_file(path)?,
+                None => vec![],
+            };
+            params.extend(self.param.iter().cloned());
+            Ok(params)
+        }
     }
 
     /// Arguments related to runtime limits
@@ -339,6 +765,23 @@ mod common_args {
         pub max_time: Option<Duration>,
     }
 
+    impl RunLimitArgs {
+        /// The configured limits, with the default values for the missing ones
+        pub fn run_limits(&self) -> RunLimits {
+            RunLimits {
+                max_facts: self
+                    .max_facts
+                    .unwrap_or_else(|| RunLimits::default().max_facts),
+                max_iterations: self
+                    .max_iterations
+                    .unwrap_or_else(|| RunLimits::default().max_iterations),
+                max_time: self
+                    .max_time
+                    .map_or_else(|| RunLimits::default().max_time, |d| d.to_std().unwrap()),
+            }
+        }
+    }
+
     /// Arguments related to running authorization
     #[derive(Parser)]
     pub struct AuthorizeArgs {
@@ -385,7 +828,7 @@ mod common_args {
             value_name("SNAPSHOT")
         )]
         pub authorize_with_snapshot: Option<String>,
-        /// Authorize the biscuit with the provided policies snapshot.
+        /// Authorize the biscuit with the provided policies snapshot, read from the given file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
         #[clap(
             long,
             conflicts_with("authorize-with"),
@@ -396,8 +839,15 @@ mod common_args {
         )]
         pub authorize_with_snapshot_file: Option<PathBuf>,
         /// Read the snapshot from a binary file
-        #[clap(long, requires("authorize-with-snapshot-file"))]
+        #[clap(
+            long,
+            requires("authorize-with-snapshot-file"),
+            conflicts_with("authorize-with-base64-snapshot-file")
+        )]
         pub authorize_with_raw_snapshot_file: bool,
+        /// Only accept a base64-encoded snapshot file. By default, the encoding is detected automatically
+        #[clap(long, requires("authorize-with-snapshot-file"))]
+        pub authorize_with_base64_snapshot_file: bool,
         /// Include the current time in the verifier facts
         #[clap(long)]
         pub include_time: bool,
@@ -432,15 +882,251 @@ mod common_args {
         pub add_ttl: Option<Ttl>,
     }
 
+    /// Arguments related to defining and signing a third-party block
+    #[derive(Parser)]
+    pub struct ThirdPartyBlockArgs {
+        #[clap(flatten)]
+        pub block_args: BlockArgs,
+        #[clap(flatten)]
+        pub restriction_args: RestrictionArgs,
+        #[clap(flatten)]
+        pub param_arg: ParamArg,
+        /// Only sign the block if it is allowed by the signing policy read from the given TOML file: allowed predicates, required checks, maximum TTL, and rules (forbidden entirely, or by the name of their head predicate)
+        #[clap(long, parse(from_os_str), value_name("POLICY_FILE"))]
+        pub policy: Option<PathBuf>,
+    }
+
+    impl ThirdPartyBlockArgs {
+        /// Where to read the datalog of the block from. With restrictions, the datalog is optional
+        pub fn datalog_input(&self) -> DatalogInput {
+            match (&self.block_args.block_file, &self.block_args.block) {
+                (Some(file), None) => DatalogInput::FromFile(file.to_path_buf()),
+                (None, Some(str)) => DatalogInput::DatalogString(str.to_owned()),
+                (None, None) if self.restriction_args.has_restrictions() => {
+                    DatalogInput::DatalogString(String::new())
+                }
+                (None, None) => DatalogInput::FromEditor,
+                // the other combinations are prevented by clap
+                _ => unreachable!(),
+            }
+        }
+
+        /// Builds the block from its datalog, restrictions and TTL, and makes sure it is
+        /// allowed by the signing policy, if any
+        pub fn block_builder(&self, block_from: &DatalogInput) -> anyhow::Result<BlockBuilder> {
+            let restrictions = self.restriction_args.restrictions()?;
+            let params = self.param_arg.params()?;
+            let mut referenced = ParamNames::default();
+            let mut builder = read_block_from(
+                block_from,
+                &params,
+                &mut referenced,
+                &self.block_args.context,
+                BlockBuilder::new(),
+            )?;
+            builder = read_block_from(
+                &DatalogInput::DatalogString(restrictions),
+                &params,
+                &mut referenced,
+                &None,
+                builder,
+            )?;
+            ensure_params_used(&params, &referenced, self.param_arg.strict_params)?;
+
+            if let Some(ttl) = &self.block_args.add_ttl {
+                builder = builder.check_expiration_date(ttl.to_datetime().into());
+            }
+
+            if let Some(policy) = &self.policy {
+                read_signing_policy(policy)?.ensure_allowed(&BlockCode::from_builder(&builder)?)?;
+            }
+            Ok(builder)
+        }
+    }
+
+    /// Arguments related to defining several blocks, in order
+    #[derive(Parser)]
+    pub struct BlocksArgs {
+        /// A block to append to the token. `--block` and `--block-file` can be repeated to append several blocks, in order. If they are omitted, an interactive $EDITOR will be opened.
+        #[clap(long, value_name("DATALOG"))]
+        pub block: Vec<String>,
+        /// A block to append to the token, read from the given file. `--block` and `--block-file` can be repeated to append several blocks, in order. If they are omitted, an interactive $EDITOR will be opened.
+        #[clap(long, parse(from_os_str), value_name("DATALOG_FILE"))]
+        pub block_file: Vec<PathBuf>,
+        /// The optional context string attached to the preceding block (or to the first block, when provided before any block)
+        #[clap(long)]
+        pub context: Vec<String>,
+        /// Add a TTL check to the preceding block (or to the first block, when provided before any block). You can either provide an expiration timestamp or a duration
+        ///
+        /// [examples: 2025-04-01T00:00:00Z, 1d, 15m]
+        #[clap(
+            long,
+            parse(try_from_str = parse_ttl),
+            value_name("TTL"),
+            verbatim_doc_comment
+        )]
+        pub add_ttl: Vec<Ttl>,
+    }
+
+    impl BlocksArgs {
+        /// The blocks to append, in command-line order, each with the context and TTL that follow it.
+        /// `default` is used when no block is provided.
+        pub fn blocks(
+            &self,
+            matches: &ArgMatches,
+            default: DatalogInput,
+        ) -> anyhow::Result<Vec<BlockDefinition>> {
+            let indices = |id: &str| matches.indices_of(id).into_iter().flatten();
+
+            let mut sources = indices("block")
+                .zip(
+                    self.block
+                        .iter()
+                        .map(|b| DatalogInput::DatalogString(b.clone())),
+                )
+                .chain(
+                    indices("block-file").zip(
+                        self.block_file
+                            .iter()
+                            .map(|f| DatalogInput::FromFile(f.clone())),
+                    ),
+                )
+                .collect::<Vec<_>>();
+            sources.sort_by_key(|(index, _)| *index);
+            if sources.is_empty() {
+                sources.push((0, default));
+            }
+
+            // position of the last block provided before the given index
+            let block_before = |index: usize| {
+                sources
+                    .iter()
+                    .filter(|(i, _)| *i < index)
+                    .count()
+                    .saturating_sub(1)
+            };
+            let mut contexts = vec![None; sources.len()];
+            for (index, context) in indices("context").zip(&self.context) {
+                if contexts[block_before(index)]
+                    .replace(context.clone())
+                    .is_some()
+                {
+                    anyhow::bail!("Only one `--context` can be attached to each block");
+                }
+            }
+            let mut ttls = vec![None; sources.len()];
+            for (index, ttl) in indices("add-ttl").zip(&self.add_ttl) {
+                if ttls[block_before(index)].replace(ttl.clone()).is_some() {
+                    anyhow::bail!("Only one `--add-ttl` can be attached to each block");
+                }
+            }
+
+            Ok(sources
+                .into_iter()
+                .zip(contexts.into_iter().zip(ttls))
+                .map(|((_, from), (context, ttl))| BlockDefinition { from, context, ttl })
+                .collect())
+        }
+    }
+
+    /// Arguments related to adding common restrictions to a block
+    #[derive(Parser)]
+    pub struct RestrictionArgs {
+        /// Restrict the token to the given operation (can be repeated). The authorizer must provide the current operation as an `operation("…")` fact
+        #[clap(long, value_name("OPERATION"))]
+        pub restrict_operation: Vec<String>,
+        /// Restrict the token to resources starting with the given prefix (can be repeated). The authorizer must provide the current resource as a `resource("…")` fact
+        #[clap(long, value_name("RESOURCE_PREFIX"))]
+        pub restrict_resource: Vec<String>,
+        /// Restrict the token to the given client IP address (can be repeated). The authorizer must provide the client IP address as a `client_ip_address("…")` fact
+        #[clap(long, value_name("IP_ADDRESS"))]
+        pub restrict_ip: Vec<IpAddr>,
+        /// Add the datalog from a preset, read from `PRESET_DIR/PRESET.datalog` (can be repeated). Presets can contain datalog parameters, provided with `--param`
+        #[clap(long, value_name("PRESET"), requires("preset-dir"))]
+        pub preset: Vec<String>,
+        /// The directory containing attenuation presets
+        #[clap(long, parse(from_os_str), value_name("PRESET_DIR"))]
+        pub preset_dir: Option<PathBuf>,
+    }
+
+    impl RestrictionArgs {
+        pub fn has_restrictions(&self) -> bool {
+            !self.restrict_operation.is_empty()
+                || !self.restrict_resource.is_empty()
+                || !self.restrict_ip.is_empty()
+                || !self.preset.is_empty()
+        }
+
+        /// Datalog checks from the restriction flags, followed by the presets contents
+        pub fn restrictions(&self) -> anyhow::Result<String> {
+            let mut code = restriction_checks(
+                &self.restrict_operation,
+                &self.restrict_resource,
+                &self.restrict_ip,
+            );
+            if let Some(dir) = &self.preset_dir {
+                for preset in &self.preset {
+                    code.push_str(&read_preset(dir, preset)?);
+                    code.push('\n');
+                }
+            }
+            Ok(code)
+        }
+    }
+
     /// Arguments related to reading a biscuit
     #[derive(Parser)]
     pub struct BiscuitInputArgs {
-        /// Read the biscuit from the given file (or use `-` to read from stdin)
+        /// Read the biscuit from the given file (or use `-` to read from stdin, `env:VAR` to read from an environment variable, or `fd:N` to read from a file descriptor)
         #[clap(parse(from_os_str))]
         pub biscuit_file: PathBuf,
         /// Read the biscuit raw bytes directly, with no base64 parsing
-        #[clap(long)]
+        #[clap(long, conflicts_with("base64-input"))]
         pub raw_input: bool,
+        /// Only accept a base64-encoded biscuit. By default, the encoding is detected automatically
+        #[clap(long)]
+        pub base64_input: bool,
+        /// Extract the biscuit from an HTTP request dump, a header line (eg `Authorization: Bearer …`), a cookie string or a URL
+        #[clap(
+            long,
+            value_enum,
+            conflicts_with("raw-input"),
+            conflicts_with("base64-input")
+        )]
+        pub extract_from: Option<ExtractFrom>,
+        /// Name of the header containing the biscuit, when extracting it from an HTTP request or a header
+        #[clap(long, default_value("Authorization"), value_name("HEADER"))]
+        pub header_name: String,
+        /// Name of the cookie containing the biscuit, when extracting it from an HTTP request or a cookie string
+        #[clap(long, default_value("biscuit"), value_name("COOKIE"))]
+        pub cookie_name: String,
+        /// Name of the query parameter containing the biscuit, when extracting it from an HTTP request or a URL
+        #[clap(long, default_value("token"), value_name("PARAM"))]
+        pub query_param_name: String,
+    }
+
+    impl BiscuitInputArgs {
+        pub fn biscuit_format(&self) -> BiscuitFormat {
+            match self.extract_from {
+                None => BiscuitFormat::from_flags(self.raw_input, self.base64_input),
+                Some(ExtractFrom::HttpRequest) => {
+                    BiscuitFormat::Extracted(TokenLocation::HttpRequest {
+                        header: self.header_name.clone(),
+                        cookie: self.cookie_name.clone(),
+                        query_param: self.query_param_name.clone(),
+                    })
+                }
+                Some(ExtractFrom::Header) => {
+                    BiscuitFormat::Extracted(TokenLocation::Header(self.header_name.clone()))
+                }
+                Some(ExtractFrom::Cookie) => {
+                    BiscuitFormat::Extracted(TokenLocation::Cookie(self.cookie_name.clone()))
+                }
+                Some(ExtractFrom::Url) => {
+                    BiscuitFormat::Extracted(TokenLocation::Url(self.query_param_name.clone()))
+                }
+            }
+        }
     }
 
     /// Arguments related to reading a private key for signing a block
@@ -449,7 +1135,7 @@ mod common_args {
         /// The private key used to sign the block
         #[clap(long, required_unless_present("private-key-file"))]
         pub private_key: Option<String>,
-        /// The private key used to sign the block
+        /// The private key used to sign the block, read from the given file (or use `env:VAR` to read it from an environment variable, or `fd:N` to read it from a file descriptor)
         #[clap(
             long,
             parse(from_os_str),
@@ -469,6 +1155,73 @@ mod common_args {
         )]
         pub private_key_algorithm: Option<Algorithm>,
     }
+
+    impl PrivateKeyArgs {
+        pub fn key_bytes(&self) -> KeyBytes {
+            match (
+                &self.private_key,
+                &self.private_key_file,
+                &self.private_key_format,
+            ) {
+                (Some(str), None, KeyFormat::Hex) => KeyBytes::HexString(str.to_owned()),
+                (Some(str), None, KeyFormat::Pem) => KeyBytes::PemString(str.to_owned()),
+                (None, Some(file), f) => key_bytes_from_path(file, *f),
+                // the other combinations are prevented by clap
+                _ => unreachable!(),
+            }
+        }
+
+        pub fn private_key(&self) -> anyhow::Result<PrivateKey> {
+            read_private_key_from(&self.key_bytes(), &self.private_key_algorithm)
+        }
+    }
+
+    /// Arguments related to reading a key which can be private or public
+    #[derive(Parser)]
+    pub struct AnyKeyArgs {
+        /// Read the key from the given file (or use `-` to read it from stdin, `env:VAR` to read it from an environment variable, or `fd:N` to read it from a file descriptor)
+        #[clap(
+            parse(from_os_str),
+            value_name("KEY_FILE"),
+            required_unless_present("key")
+        )]
+        pub key_file: Option<PathBuf>,
+        /// The key, as a string
+        #[clap(long, conflicts_with("key-file"))]
+        pub key: Option<String>,
+        /// Input format for the key. If omitted, hex and PEM keys are detected
+        #[clap(long, value_enum)]
+        pub key_format: Option<KeyFormat>,
+        /// Specify the key algorithm, required when reading raw bytes
+        #[clap(long, value_enum, value_name("KEY_ALGORITHM"))]
+        pub key_algorithm: Option<Algorithm>,
+        /// Whether the key is private or public, when it can't be detected (ed25519 hex and raw keys). ed25519 hex keys are read as public keys unless `--key-kind private` is set
+        #[clap(long, value_enum)]
+        pub key_kind: Option<KeyKind>,
+    }
+
+    impl AnyKeyArgs {
+        pub fn read_key(&self) -> anyhow::Result<(KeyFormat, AnyKey)> {
+            let format = self.key_format.unwrap_or_default();
+            let key_from = match (&self.key, &self.key_file, format) {
+                (Some(_), None, KeyFormat::Raw) => {
+                    anyhow::bail!("raw key input is only allowed from a file or stdin")
+                }
+                (Some(str), None, KeyFormat::Hex) => KeyBytes::HexString(str.to_owned()),
+                (Some(str), None, KeyFormat::Pem) => KeyBytes::PemString(str.to_owned()),
+                (None, Some(path), f) if path == &PathBuf::from("-") => KeyBytes::FromStdin(f),
+                (None, Some(path), f) => key_bytes_from_path(path, f),
+                // the other combinations are prevented by clap
+                _ => unreachable!(),
+            };
+            read_any_key(
+                &key_from,
+                self.key_format.is_none(),
+                &self.key_algorithm,
+                self.key_kind,
+            )
+        }
+    }
 }
 
 #[test]
@@ -476,3 +1229,66 @@ fn verify_cli() {
     use clap::CommandFactory;
     Opts::command().debug_assert();
 }
+
+#[test]
+fn attenuate_blocks_order() {
+    use clap::{CommandFactory, FromArgMatches};
+    let matches = Opts::command().get_matches_from([
+        "biscuit",
+        "attenuate",
+        "--context",
+        "first",
+        "--block",
+        "a(true);",
+        "--add-ttl",
+        "1h",
+        "--block-file",
+        "b.datalog",
+        "--block",
+        "c(true);",
+        "--context",
+        "third",
+        "-",
+    ]);
+    let matches = matches.subcommand_matches("attenuate").unwrap();
+    let attenuate = Attenuate::from_arg_matches(matches).unwrap();
+    let blocks = attenuate
+        .blocks_args
+        .blocks(matches, DatalogInput::FromEditor)
+        .unwrap();
+
+    let description = blocks
+        .iter()
+        .map(|b| {
+            let from = match &b.from {
+                DatalogInput::DatalogString(s) => s.clone(),
+                DatalogInput::FromFile(f) => f.display().to_string(),
+                _ => unreachable!(),
+            };
+            (from, b.context.clone(), b.ttl.is_some())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        description,
+        vec![
+            ("a(true);".to_string(), Some("first".to_string()), true),
+            ("b.datalog".to_string(), None, false),
+            ("c(true);".to_string(), Some("third".to_string()), false),
+        ]
+    );
+}
+
+#[test]
+fn block_contents_encoding_flags() {
+    let parse = |args: &[&str]| {
+        AppendThirdPartyBlock::try_parse_from(
+            ["append-third-party-block"]
+                .iter()
+                .chain(args)
+                .chain(&["-"]),
+        )
+    };
+    assert!(parse(&["--block-contents-file", "block", "--base64-block-contents"]).is_ok());
+    assert!(parse(&["--block-contents", "abc", "--base64-block-contents"]).is_err());
+    assert!(parse(&["--block-contents", "abc", "--raw-block-contents"]).is_err());
+}
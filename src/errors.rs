@@ -18,6 +18,19 @@ pub enum CliError {
     MultipleStdinsConflict,
     #[error("Can't read binary content from an interactive terminal. Please pipe the content or use a proper file.")]
     BinaryFromTTY,
+    #[error("Can't read binary content from an environment variable. Please use a text encoding instead.")]
+    BinaryFromEnv,
+    #[error("environment variable not found: {0}")]
+    EnvVarNotFound(String),
+    #[error("file descriptor is not open for reading: {0}")]
+    FdNotOpen(u32),
+    #[cfg(not(unix))]
+    #[error("reading from a file descriptor is only supported on unix systems: fd:{0}")]
+    FdUnsupported(u32),
+    #[error("Could not find a biscuit in {0}")]
+    TokenNotFound(String),
+    #[error("preset not found: {0} ({1})")]
+    UnknownPreset(String, PathBuf),
     #[error("Can't start an editor outside of an interactive terminal")]
     EditorOutsideTTY,
     #[error("Failed reading the datalog temporary file")]
@@ -26,18 +39,46 @@ pub enum CliError {
     FailedParsingEditorEnvVar,
     #[error("Failed to parse {0}: {1}")]
     ParseError(String, String),
+    #[error("Missing datalog parameters: {0}")]
+    MissingParams(String),
+    #[error("Missing datalog parameters: {0}. Term parameters are provided with `--param name=value` for a string, or `--param name:type=value` where type is integer, date, bytes, bool, set or set<type>")]
+    MissingTermParams(String),
+    #[error("Unused datalog parameters: {0}")]
+    UnusedParams(String),
     #[error("Duration outside representable intervals")]
     InvalidDuration,
     #[error("A public key is required when authorizing a biscuit")]
     MissingPublicKeyForAuthorization,
     #[error("A public key is required when querying a biscuit")]
     MissingPublicKeyForQuerying,
+    #[error("A public key is required when checking external keys")]
+    MissingPublicKeyForExternalKeys,
+    #[error("Can't tell whether the key is private or public, please use --key-kind")]
+    AmbiguousKeyKind,
+    #[error("Inconsistent algorithm: key algorithm is {0}, expected algorithm is {1}")]
+    InconsistentKeyAlgorithm(String, String),
     #[error("Signatures check failed")]
     SignaturesCheckFailed,
+    #[error("External keys check failed")]
+    ExternalKeysCheckFailed,
     #[error("Datalog fact generation failed")]
     EvaluationFailed,
     #[error("Authorization failed")]
     AuthorizationFailed,
     #[error("Querying failed")]
     QueryFailed,
+    #[error("The attenuated biscuit does not extend the original biscuit")]
+    NotAnAttenuation,
+    #[error(
+        "The attenuated biscuit is authorized in {0} case(s) where the original biscuit is not"
+    )]
+    AttenuationWidensRights(usize),
+    #[error("The third-party block request was not generated from the last block of the biscuit")]
+    RequestMismatch,
+    #[error("The third-party block signature check failed: {0}")]
+    ThirdPartyBlockMismatch(String),
+    #[error("The biscuit is sealed, no block can be appended to it")]
+    SealedBiscuit,
+    #[error("The block is not allowed by the signing policy: {0}")]
+    PolicyViolation(String),
 }
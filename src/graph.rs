@@ -0,0 +1,310 @@
+/*
+ * SPDX-FileCopyrightText: 2021 Clément Delafargue <clement@delafargue.name>
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+use biscuit_auth::builder::{Rule, Scope};
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+
+use crate::input::*;
+use crate::scopes::trusted_blocks;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz
+    Dot,
+    /// Mermaid flowchart
+    Mermaid,
+}
+
+/// A token block, along with the key it was signed with, for third-party blocks
+pub struct GraphBlock {
+    pub code: BlockCode,
+    pub external_key: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Shape {
+    Box,
+    Predicate,
+    Check,
+}
+
+struct Edge {
+    from: String,
+    to: String,
+    label: String,
+    dashed: bool,
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<(String, String, Shape)>,
+    edges: Vec<Edge>,
+    predicates: BTreeMap<String, String>,
+}
+
+impl Graph {
+    fn node(&mut self, id: String, label: String, shape: Shape) {
+        self.nodes.push((id, label, shape));
+    }
+
+    fn edge(&mut self, from: &str, to: &str, label: &str, dashed: bool) {
+        self.edges.push(Edge {
+            from: from.to_string(),
+            to: to.to_string(),
+            label: label.to_string(),
+            dashed,
+        });
+    }
+
+    fn predicate(&mut self, name: &str) -> String {
+        if let Some(id) = self.predicates.get(name) {
+            return id.clone();
+        }
+        let id = format!("pred{}", self.predicates.len());
+        self.predicates.insert(name.to_string(), id.clone());
+        self.node(id.clone(), name.to_string(), Shape::Predicate);
+        id
+    }
+
+    /// Public keys trusted by a scope but not used to sign any block
+    fn key(&mut self, key: String) -> String {
+        if let Some((id, _, _)) = self
+            .nodes
+            .iter()
+            .find(|(id, label, _)| id.starts_with("key") && *label == key)
+        {
+            return id.clone();
+        }
+        let id = format!("key{}", self.nodes.len());
+        self.node(id.clone(), key, Shape::Predicate);
+        id
+    }
+
+    fn rule(&mut self, rule: &Rule, origin: &str) {
+        let head = self.predicate(&rule.head.name);
+        for p in &rule.body {
+            let body = self.predicate(&p.name);
+            self.edge(&body, &head, origin, false);
+        }
+    }
+
+    fn dot(&self) -> String {
+        let mut out = String::from("digraph biscuit {\n  node [fontname=\"monospace\"];\n");
+        for (id, label, shape) in &self.nodes {
+            let shape = match shape {
+                Shape::Box => "box",
+                Shape::Predicate => "ellipse",
+                Shape::Check => "hexagon",
+            };
+            out.push_str(&format!(
+                "  {} [label=\"{}\", shape={}];\n",
+                id,
+                dot_escape(label),
+                shape
+            ));
+        }
+        for e in &self.edges {
+            let mut attributes = vec![];
+            if !e.label.is_empty() {
+                attributes.push(format!("label=\"{}\"", dot_escape(&e.label)));
+            }
+            if e.dashed {
+                attributes.push("style=dashed".to_string());
+            }
+            if attributes.is_empty() {
+                out.push_str(&format!("  {} -> {};\n", e.from, e.to));
+            } else {
+                out.push_str(&format!(
+                    "  {} -> {} [{}];\n",
+                    e.from,
+                    e.to,
+                    attributes.join(", ")
+                ));
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn mermaid(&self) -> String {
+        let mut out = String::from("flowchart TD\n");
+        for (id, label, shape) in &self.nodes {
+            let label = mermaid_escape(label);
+            let node = match shape {
+                Shape::Box => format!("{}[\"{}\"]", id, label),
+                Shape::Predicate => format!("{}([\"{}\"])", id, label),
+                Shape::Check => format!("{}{{{{\"{}\"}}}}", id, label),
+            };
+            out.push_str(&format!("  {}\n", node));
+        }
+        for e in &self.edges {
+            let arrow = if e.dashed { "-.->" } else { "-->" };
+            if e.label.is_empty() {
+                out.push_str(&format!("  {} {} {}\n", e.from, arrow, e.to));
+            } else {
+                out.push_str(&format!(
+                    "  {} {}|\"{}\"| {}\n",
+                    e.from,
+                    arrow,
+                    mermaid_escape(&e.label),
+                    e.to
+                ));
+            }
+        }
+        out
+    }
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn mermaid_escape(s: &str) -> String {
+    s.replace('"', "#quot;").replace('\n', "<br>")
+}
+
+fn block_name(index: usize) -> String {
+    if index == 0 {
+        "authority".to_string()
+    } else {
+        format!("block {}", index)
+    }
+}
+
+/// Nodes trusted by a block, on top of itself and the authorizer. Keys which
+/// do not sign any block, and unresolved parameters, get their own node
+fn trusted_nodes(
+    scopes: &[Scope],
+    index: usize,
+    external_keys: &[Option<String>],
+    graph: &mut Graph,
+) -> Vec<String> {
+    let mut trusted = trusted_blocks(scopes, index, external_keys)
+        .into_iter()
+        .map(|i| format!("block{}", i))
+        .collect::<Vec<_>>();
+    for scope in scopes {
+        match scope {
+            Scope::PublicKey(key) if !external_keys.contains(&Some(key.to_string())) => {
+                trusted.push(graph.key(key.to_string()))
+            }
+            Scope::Parameter(name) => trusted.push(graph.key(format!("{{{}}}", name))),
+            _ => {}
+        }
+    }
+    trusted
+}
+
+/// Renders the block chain, with trust scopes, and the dependencies between the
+/// predicates defined and used by the token (and the authorizer, if provided)
+pub fn render_graph(
+    blocks: &[GraphBlock],
+    authorizer: Option<&BlockCode>,
+    format: GraphFormat,
+) -> String {
+    let mut graph = Graph::default();
+    let external_keys = blocks
+        .iter()
+        .map(|b| b.external_key.clone())
+        .collect::<Vec<_>>();
+
+    for (i, block) in blocks.iter().enumerate() {
+        let label = match (i, &block.external_key) {
+            (0, _) => "Authority block".to_string(),
+            (_, Some(key)) => format!("Block n°{} (third party)\nsigned by {}", i, key),
+            (_, None) => format!("Block n°{}", i),
+        };
+        graph.node(format!("block{}", i), label, Shape::Box);
+    }
+    if authorizer.is_some() {
+        graph.node(
+            "authorizer".to_string(),
+            "Authorizer".to_string(),
+            Shape::Box,
+        );
+    }
+
+    for i in 1..blocks.len() {
+        graph.edge(
+            &format!("block{}", i - 1),
+            &format!("block{}", i),
+            "next",
+            false,
+        );
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        for trusted in trusted_nodes(&block.code.scopes, i, &external_keys, &mut graph) {
+            graph.edge(&format!("block{}", i), &trusted, "trusts", true);
+        }
+    }
+    if let Some(authorizer) = authorizer {
+        for trusted in trusted_nodes(&authorizer.scopes, blocks.len(), &external_keys, &mut graph) {
+            graph.edge("authorizer", &trusted, "trusts", true);
+        }
+    }
+
+    let sources = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (format!("block{}", i), block_name(i), &b.code))
+        .chain(authorizer.map(|a| ("authorizer".to_string(), "authorizer".to_string(), a)));
+    for (index, (id, name, code)) in sources.enumerate() {
+        for fact in &code.facts {
+            let predicate = graph.predicate(&fact.predicate.name);
+            graph.edge(&id, &predicate, "fact", false);
+        }
+        for rule in &code.rules {
+            graph.rule(rule, &name);
+        }
+        let checks = code
+            .checks
+            .iter()
+            .map(|c| (c.to_string(), &c.queries))
+            .chain(code.policies.iter().map(|p| (p.to_string(), &p.queries)));
+        for (n, (check, queries)) in checks.enumerate() {
+            let check_id = format!("{}_check{}", id, n);
+            graph.node(check_id.clone(), check, Shape::Check);
+            graph.edge(&id, &check_id, "", false);
+            for query in queries {
+                for p in &query.body {
+                    let predicate = graph.predicate(&p.name);
+                    graph.edge(&predicate, &check_id, "", false);
+                }
+                // queries with their own `trusting` annotation override the block scopes
+                if !query.scopes.is_empty() {
+                    for trusted in trusted_nodes(&query.scopes, index, &external_keys, &mut graph) {
+                        graph.edge(&check_id, &trusted, "trusts", true);
+                    }
+                }
+            }
+        }
+    }
+
+    match format {
+        GraphFormat::Dot => graph.dot(),
+        GraphFormat::Mermaid => graph.mermaid(),
+    }
+}
+
+#[test]
+fn graph_trust_scopes() {
+    let blocks = vec![
+        GraphBlock {
+            code: BlockCode::parse("user(\"1234\");").unwrap(),
+            external_key: None,
+        },
+        GraphBlock {
+            code: BlockCode::parse("trusting previous; check if user($u);").unwrap(),
+            external_key: None,
+        },
+    ];
+    let graph = render_graph(&blocks, None, GraphFormat::Mermaid);
+    assert!(graph.contains("  block0 -->|\"next\"| block1\n"));
+    assert!(graph.contains("  block1 -.->|\"trusts\"| block0\n"));
+    assert!(graph.contains("  pred0 --> block1_check0\n"));
+}
@@ -6,39 +6,73 @@
 use anyhow::{bail, Result};
 use atty::Stream;
 use biscuit_auth::{
-    builder::{BiscuitBuilder, BlockBuilder, Rule, Term},
+    builder::{BiscuitBuilder, BlockBuilder, Check, Fact, Policy, Rule, Scope, Term},
+    format::schema,
     Authorizer, AuthorizerBuilder, PrivateKey, PublicKey, ThirdPartyRequest, UnverifiedBiscuit,
 };
 use chrono::{DateTime, Duration, Utc};
 use clap::{PossibleValue, ValueEnum};
 use parse_duration as duration_parser;
+use prost::Message;
 use std::fs;
 use std::io::{self, Read};
-use std::path::PathBuf;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::{collections::HashMap, convert::TryInto};
+use std::{
+    collections::{BTreeSet, HashMap},
+    convert::TryInto,
+};
 use std::{env, fmt::Display};
 
 use crate::errors::CliError::*;
+use crate::keys::KeyPattern;
 
 pub enum BiscuitFormat {
     RawBiscuit,
     Base64Biscuit,
+    AutoDetect,
+    Extracted(TokenLocation),
+}
+
+/// Where to find a biscuit in HTTP-related text
+pub enum TokenLocation {
+    HttpRequest {
+        header: String,
+        cookie: String,
+        query_param: String,
+    },
+    Header(String),
+    Cookie(String),
+    Url(String),
 }
 
 #[derive(PartialEq, Clone, Copy, Debug, ValueEnum)]
+pub enum ExtractFrom {
+    HttpRequest,
+    Header,
+    Cookie,
+    Url,
+}
+
+impl BiscuitFormat {
+    pub fn from_flags(raw_input: bool, base64_input: bool) -> Self {
+        match (raw_input, base64_input) {
+            (true, _) => Self::RawBiscuit,
+            (false, true) => Self::Base64Biscuit,
+            (false, false) => Self::AutoDetect,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug, Default, ValueEnum)]
 pub enum KeyFormat {
     Raw,
+    #[default]
     Hex,
     Pem,
 }
 
-impl Default for KeyFormat {
-    fn default() -> Self {
-        Self::Hex
-    }
-}
-
 #[derive(PartialEq, Clone, Copy, Debug, Default)]
 pub struct Algorithm(pub biscuit_auth::Algorithm);
 
@@ -70,6 +104,8 @@ impl Display for Algorithm {
 pub enum BiscuitBytes {
     FromStdin(BiscuitFormat),
     FromFile(BiscuitFormat, PathBuf),
+    FromEnv(BiscuitFormat, String),
+    FromFd(BiscuitFormat, u32),
     Base64String(String),
 }
 
@@ -79,6 +115,8 @@ pub enum BiscuitBytes {
 pub enum KeyBytes {
     FromStdin(KeyFormat),
     FromFile(KeyFormat, PathBuf),
+    FromEnv(KeyFormat, String),
+    FromFd(KeyFormat, u32),
     HexString(String),
     PemString(String),
 }
@@ -90,14 +128,54 @@ pub enum DatalogInput {
     DatalogString(String),
 }
 
+/// A block to append to a token, with its optional context and TTL
+pub struct BlockDefinition {
+    pub from: DatalogInput,
+    pub context: Option<String>,
+    pub ttl: Option<Ttl>,
+}
+
 pub enum AuthorizerInput {
     FromDatalog(DatalogInput, Vec<Param>),
-    FromSnapshot(SnapshotInput),
+    FromSnapshot(BiscuitBytes),
+}
+
+/// Environment variables can be used in place of a file path, with the `env:VAR_NAME` syntax
+fn env_var_name(path: &Path) -> Option<String> {
+    path.to_str()
+        .and_then(|p| p.strip_prefix("env:"))
+        .map(|v| v.to_owned())
+}
+
+/// Open file descriptors can be used in place of a file path, with the `fd:N` syntax. `fd:0` is stdin.
+fn fd_number(path: &Path) -> Option<u32> {
+    path.to_str()
+        .and_then(|p| p.strip_prefix("fd:"))
+        .and_then(|fd| fd.parse().ok())
 }
 
-pub enum SnapshotInput {
-    FromFile(PathBuf, BiscuitFormat),
-    FromString(String),
+pub fn biscuit_bytes_from_path(path: &Path, format: BiscuitFormat) -> BiscuitBytes {
+    if path == Path::new("-") || fd_number(path) == Some(0) {
+        BiscuitBytes::FromStdin(format)
+    } else if let Some(var) = env_var_name(path) {
+        BiscuitBytes::FromEnv(format, var)
+    } else if let Some(fd) = fd_number(path) {
+        BiscuitBytes::FromFd(format, fd)
+    } else {
+        BiscuitBytes::FromFile(format, path.to_path_buf())
+    }
+}
+
+pub fn key_bytes_from_path(path: &Path, format: KeyFormat) -> KeyBytes {
+    if fd_number(path) == Some(0) {
+        KeyBytes::FromStdin(format)
+    } else if let Some(var) = env_var_name(path) {
+        KeyBytes::FromEnv(format, var)
+    } else if let Some(fd) = fd_number(path) {
+        KeyBytes::FromFd(format, fd)
+    } else {
+        KeyBytes::FromFile(format, path.to_path_buf())
+    }
 }
 
 pub fn ensure_no_input_conflict(datalog: &DatalogInput, biscuit: &BiscuitBytes) -> Result<()> {
@@ -140,6 +218,34 @@ pub fn read_stdin_bytes() -> Result<Vec<u8>> {
     Ok(buffer)
 }
 
+pub fn read_env_string(var: &str) -> Result<String> {
+    match env::var(var) {
+        Ok(value) => Ok(value.trim().to_owned()),
+        Err(env::VarError::NotPresent) => Err(EnvVarNotFound(var.to_owned()))?,
+        Err(e) => Err(ParseError(var.to_owned(), e.to_string()))?,
+    }
+}
+
+#[cfg(unix)]
+fn fd_path(fd: u32) -> Result<PathBuf> {
+    Ok(PathBuf::from(format!("/dev/fd/{}", fd)))
+}
+
+// there is no portable way to open a file descriptor by its number
+#[cfg(not(unix))]
+fn fd_path(fd: u32) -> Result<PathBuf> {
+    Err(FdUnsupported(fd))?
+}
+
+pub fn read_fd_string(fd: u32) -> Result<String> {
+    let str = fs::read_to_string(fd_path(fd)?).map_err(|_| FdNotOpen(fd))?;
+    Ok(str.trim().to_owned())
+}
+
+pub fn read_fd_bytes(fd: u32) -> Result<Vec<u8>> {
+    Ok(fs::read(fd_path(fd)?).map_err(|_| FdNotOpen(fd))?)
+}
+
 pub fn read_editor_string() -> Result<String> {
     let file = tempfile::Builder::new()
         .suffix(".biscuit-datalog")
@@ -180,6 +286,7 @@ pub fn get_editor_command() -> Result<Command> {
 pub fn read_authority_from(
     from: &DatalogInput,
     all_params: &[Param],
+    referenced: &mut ParamNames,
     context: &Option<String>,
     builder: BiscuitBuilder,
 ) -> Result<BiscuitBuilder> {
@@ -190,6 +297,11 @@ pub fn read_authority_from(
         DatalogInput::DatalogString(str) => str.to_owned(),
     };
 
+    if let Some(names) = ParamNames::from_source(&string) {
+        names.ensure_provided(all_params)?;
+        referenced.extend(names);
+    }
+
     let mut params = HashMap::new();
     let mut scope_params = HashMap::new();
     for p in all_params {
@@ -203,9 +315,13 @@ pub fn read_authority_from(
         }
     }
 
+    let scopes = block_level_scopes(&string, &scope_params)?;
     let mut builder = builder
         .code_with_params(&string, params, scope_params)
         .map_err(|e| ParseError("datalog statements".to_string(), e.to_string()))?;
+    for scope in scopes {
+        builder = builder.scope(scope);
+    }
     if let Some(ctx) = context {
         builder = builder.context(ctx.to_owned());
     }
@@ -216,6 +332,7 @@ pub fn read_authority_from(
 pub fn read_block_from(
     from: &DatalogInput,
     all_params: &[Param],
+    referenced: &mut ParamNames,
     context: &Option<String>,
     builder: BlockBuilder,
 ) -> Result<BlockBuilder> {
@@ -226,6 +343,11 @@ pub fn read_block_from(
         DatalogInput::DatalogString(str) => str.to_owned(),
     };
 
+    if let Some(names) = ParamNames::from_source(&string) {
+        names.ensure_provided(all_params)?;
+        referenced.extend(names);
+    }
+
     let mut params = HashMap::new();
     let mut scope_params = HashMap::new();
     for p in all_params {
@@ -238,9 +360,13 @@ pub fn read_block_from(
             }
         }
     }
+    let scopes = block_level_scopes(&string, &scope_params)?;
     let mut builder = builder
         .code_with_params(&string, params, scope_params)
         .map_err(|e| ParseError("datalog statements".to_string(), e.to_string()))?;
+    for scope in scopes {
+        builder = builder.scope(scope);
+    }
 
     if let Some(ctx) = context {
         builder = builder.context(ctx.to_owned());
@@ -249,10 +375,36 @@ pub fn read_block_from(
     Ok(builder)
 }
 
-pub fn read_authorizer_from(from: &AuthorizerInput) -> Result<AuthorizerBuilder> {
+/// `code_with_params` ignores the block-level `trusting` annotation, so it is
+/// applied separately
+fn block_level_scopes(
+    source: &str,
+    scope_params: &HashMap<String, PublicKey>,
+) -> Result<Vec<Scope>> {
+    let scopes = match biscuit_auth::parser::parse_block_source(source) {
+        Ok(result) => result.scopes,
+        // parsing errors are reported by `code_with_params`
+        Err(_) => return Ok(vec![]),
+    };
+    scopes
+        .into_iter()
+        .map(|scope| match scope.into() {
+            Scope::Parameter(name) => match scope_params.get(&name) {
+                Some(key) => Ok(Scope::PublicKey(*key)),
+                None => Err(MissingParams(format!("{} (pubkey)", name)).into()),
+            },
+            scope => Ok(scope),
+        })
+        .collect()
+}
+
+pub fn read_authorizer_from(
+    from: &AuthorizerInput,
+    referenced: &mut ParamNames,
+) -> Result<AuthorizerBuilder> {
     match from {
         AuthorizerInput::FromDatalog(datalog, all_params) => {
-            read_authorizer_from_datalog(datalog, all_params)
+            read_authorizer_from_datalog(datalog, all_params, referenced)
         }
         AuthorizerInput::FromSnapshot(snapshot) => read_authorizer_from_snapshot(snapshot),
     }
@@ -261,6 +413,7 @@ pub fn read_authorizer_from(from: &AuthorizerInput) -> Result<AuthorizerBuilder>
 pub fn read_authorizer_from_datalog(
     from: &DatalogInput,
     all_params: &[Param],
+    referenced: &mut ParamNames,
 ) -> Result<AuthorizerBuilder> {
     let string = match from {
         DatalogInput::FromEditor => read_editor_string()?,
@@ -269,6 +422,11 @@ pub fn read_authorizer_from_datalog(
         DatalogInput::DatalogString(str) => str.to_owned(),
     };
 
+    if let Some(names) = ParamNames::from_source(&string) {
+        names.ensure_provided(all_params)?;
+        referenced.extend(names);
+    }
+
     let mut params = HashMap::new();
     let mut scope_params = HashMap::new();
     for p in all_params {
@@ -288,25 +446,197 @@ pub fn read_authorizer_from_datalog(
     Ok(builder)
 }
 
+/// Authorizer files from a corpus: either a single datalog file, or all the `.datalog`
+/// files of a directory, in alphabetical order
+pub fn read_corpus_paths(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut paths = fs::read_dir(path)
+        .map_err(FileError)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(FileError)?;
+    paths.retain(|p| p.extension().map(|e| e == "datalog").unwrap_or(false));
+    paths.sort();
+    if paths.is_empty() {
+        bail!("No datalog files found in {}", path.display());
+    }
+    Ok(paths)
+}
+
 fn read_authorizer_from_snapshot(
-    snapshot: &SnapshotInput,
+    snapshot: &BiscuitBytes,
 ) -> std::result::Result<AuthorizerBuilder, anyhow::Error> {
-    let builder = match snapshot {
-        SnapshotInput::FromFile(path, BiscuitFormat::RawBiscuit) => {
-            AuthorizerBuilder::from_raw_snapshot(
-                &fs::read(path).map_err(|_| FileNotFound(path.clone()))?,
-            )?
-        }
-        SnapshotInput::FromFile(path, BiscuitFormat::Base64Biscuit) => {
-            AuthorizerBuilder::from_base64_snapshot(
-                fs::read_to_string(path)
-                    .map_err(|_| FileNotFound(path.clone()))?
-                    .trim(),
-            )?
-        }
-        SnapshotInput::FromString(str) => AuthorizerBuilder::from_base64_snapshot(str)?,
+    let bytes = read_bytes_from(snapshot, "base64-encoded policies snapshot")?;
+    Ok(AuthorizerBuilder::from_raw_snapshot(&bytes)?)
+}
+
+/// Datalog contents of a block, or of an authorizer, parsed back from its source
+pub struct BlockCode {
+    pub scopes: Vec<Scope>,
+    pub facts: Vec<Fact>,
+    pub rules: Vec<Rule>,
+    pub checks: Vec<Check>,
+    pub policies: Vec<Policy>,
+}
+
+impl BlockCode {
+    pub fn parse(code: &str) -> Result<Self> {
+        let result = biscuit_auth::parser::parse_block_source(code).map_err(parse_errors)?;
+        Ok(BlockCode {
+            scopes: result.scopes.into_iter().map(|s| s.into()).collect(),
+            facts: result.facts.into_iter().map(|(_, f)| f.into()).collect(),
+            rules: result.rules.into_iter().map(|(_, r)| r.into()).collect(),
+            checks: result.checks.into_iter().map(|(_, c)| c.into()).collect(),
+            policies: vec![],
+        })
+    }
+
+    /// The datalog of a block builder, with its parameters applied
+    pub fn from_builder(builder: &BlockBuilder) -> Result<Self> {
+        let mut code = Self::parse(&builder.to_string())?;
+        code.scopes = builder.scopes.clone();
+        Ok(code)
+    }
+
+    pub fn parse_authorizer(code: &str) -> Result<Self> {
+        let result = biscuit_auth::parser::parse_source(code).map_err(parse_errors)?;
+        Ok(BlockCode {
+            scopes: result.scopes.into_iter().map(|s| s.into()).collect(),
+            facts: result.facts.into_iter().map(|(_, f)| f.into()).collect(),
+            rules: result.rules.into_iter().map(|(_, r)| r.into()).collect(),
+            checks: result.checks.into_iter().map(|(_, c)| c.into()).collect(),
+            policies: result.policies.into_iter().map(|(_, p)| p.into()).collect(),
+        })
+    }
+}
+
+/// Parses the datalog of all the blocks of a token
+pub fn token_blocks(biscuit: &UnverifiedBiscuit) -> Result<Vec<BlockCode>> {
+    let scopes = block_scopes(&biscuit.to_vec()?)?;
+    (0..biscuit.block_count())
+        .zip(scopes)
+        .map(|(i, scopes)| {
+            let mut code = BlockCode::parse(&biscuit.print_block_source(i)?)?;
+            code.scopes = scopes;
+            Ok(code)
+        })
+        .collect()
+}
+
+/// Block-level `trusting` annotations are not part of the printed block source,
+/// so they are read from the serialized blocks
+fn block_scopes(bytes: &[u8]) -> Result<Vec<Vec<Scope>>> {
+    let token = schema::Biscuit::decode(bytes)
+        .map_err(|e| ParseError("biscuit".to_string(), e.to_string()))?;
+    let blocks = std::iter::once(&token.authority)
+        .chain(token.blocks.iter())
+        .map(|signed_block| {
+            let block = schema::Block::decode(&signed_block.block[..])
+                .map_err(|e| ParseError("block".to_string(), e.to_string()))?;
+            Ok((block, signed_block.external_signature.is_some()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // first-party blocks refer to the public keys of the whole token, while
+    // third-party blocks refer to their own public keys
+    let mut token_keys = vec![];
+    for (block, _) in &blocks {
+        for key in &block.public_keys {
+            let key = proto_public_key(key)?;
+            if !token_keys.contains(&key) {
+                token_keys.push(key);
+            }
+        }
+    }
+
+    blocks
+        .iter()
+        .map(|(block, third_party)| {
+            let keys = if *third_party {
+                block
+                    .public_keys
+                    .iter()
+                    .map(proto_public_key)
+                    .collect::<Result<Vec<_>>>()?
+            } else {
+                token_keys.clone()
+            };
+            proto_scopes(&block.scope, &keys)
+        })
+        .collect()
+}
+
+/// Authorizer-level `trusting` annotations are not part of the dumped authorizer code
+/// either, so they are read from a snapshot of the authorizer
+pub fn authorizer_scopes(builder: &AuthorizerBuilder) -> Result<Vec<Scope>> {
+    let world = builder.snapshot()?.world;
+    let keys = world
+        .public_keys
+        .iter()
+        .map(proto_public_key)
+        .collect::<Result<Vec<_>>>()?;
+    proto_scopes(&world.authorizer_block.scope, &keys)
+}
+
+/// Decodes the `trusting` annotation of a serialized block, with the public keys
+/// its scopes refer to
+pub fn proto_scopes(scopes: &[schema::Scope], keys: &[PublicKey]) -> Result<Vec<Scope>> {
+    scopes
+        .iter()
+        .map(|scope| match scope.content {
+            Some(schema::scope::Content::ScopeType(0)) => Ok(Scope::Authority),
+            Some(schema::scope::Content::ScopeType(1)) => Ok(Scope::Previous),
+            Some(schema::scope::Content::PublicKey(i)) => keys
+                .get(i as usize)
+                .map(|key| Scope::PublicKey(*key))
+                .ok_or_else(|| ParseError("block".to_string(), "invalid scope".to_string()).into()),
+            _ => Err(ParseError("block".to_string(), "invalid scope".to_string()).into()),
+        })
+        .collect()
+}
+
+pub fn proto_public_key(key: &schema::PublicKey) -> Result<PublicKey> {
+    let algorithm = match key.algorithm {
+        1 => biscuit_auth::Algorithm::Secp256r1,
+        _ => biscuit_auth::Algorithm::Ed25519,
     };
-    Ok(builder)
+    Ok(PublicKey::from_bytes(&key.key, algorithm)?)
+}
+
+fn parse_errors(errors: Vec<biscuit_auth::parser::Error>) -> anyhow::Error {
+    ParseError(
+        "datalog statements".to_string(),
+        errors
+            .iter()
+            .map(|e| e.input.to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
+    .into()
+}
+
+impl Display for BlockCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.scopes.is_empty() {
+            let scopes = self.scopes.iter().map(|s| s.to_string());
+            writeln!(f, "trusting {};", scopes.collect::<Vec<_>>().join(", "))?;
+        }
+        for fact in &self.facts {
+            writeln!(f, "{};", fact)?;
+        }
+        for rule in &self.rules {
+            writeln!(f, "{};", rule)?;
+        }
+        for check in &self.checks {
+            writeln!(f, "{};", check)?;
+        }
+        for policy in &self.policies {
+            writeln!(f, "{};", policy)?;
+        }
+        Ok(())
+    }
 }
 
 fn read_pem_private_key(str: &str, alg: &Option<Algorithm>) -> Result<PrivateKey> {
@@ -352,13 +682,33 @@ pub fn read_private_key_from(from: &KeyBytes, alg: &Option<Algorithm>) -> Result
             read_pem_private_key(&str, alg)
                 .map_err(|e| ParseError("private key".to_string(), format!("{}", &e)))?
         }
+        (KeyBytes::FromEnv(KeyFormat::Hex, var), None) => read_env_string(var)?
+            .parse()
+            .map_err(|e| ParseError("private key".to_string(), format!("{}", &e)))?,
+        (KeyBytes::FromEnv(KeyFormat::Pem, var), None) => {
+            read_pem_private_key(&read_env_string(var)?, alg)
+                .map_err(|e| ParseError("private key".to_string(), format!("{}", &e)))?
+        }
+        (KeyBytes::FromEnv(KeyFormat::Raw, _), _) => Err(BinaryFromEnv)?,
+        (KeyBytes::FromFd(KeyFormat::Raw, fd), Some(alg)) => {
+            PrivateKey::from_bytes(&read_fd_bytes(*fd)?, alg.0)
+                .map_err(|e| ParseError("private key".to_string(), format!("{}", &e)))?
+        }
+        (KeyBytes::FromFd(KeyFormat::Hex, fd), None) => read_fd_string(*fd)?
+            .parse()
+            .map_err(|e| ParseError("private key".to_string(), format!("{}", &e)))?,
+        (KeyBytes::FromFd(KeyFormat::Pem, fd), None) => {
+            read_pem_private_key(&read_fd_string(*fd)?, alg)
+                .map_err(|e| ParseError("private key".to_string(), format!("{}", &e)))?
+        }
         (KeyBytes::HexString(str), None) => str
             .parse()
             .map_err(|e| ParseError("private key".to_string(), format!("{}", &e)))?,
         (KeyBytes::PemString(str), None) => read_pem_private_key(str, alg)
             .map_err(|e| ParseError("private key".to_string(), format!("{}", &e)))?,
         (KeyBytes::FromStdin(KeyFormat::Raw), None)
-        | (KeyBytes::FromFile(KeyFormat::Raw, _), None) => {
+        | (KeyBytes::FromFile(KeyFormat::Raw, _), None)
+        | (KeyBytes::FromFd(KeyFormat::Raw, _), None) => {
             bail!("Raw private key binary input requires an explicit key algorithm")
         }
         (_, Some(_)) => {
@@ -419,13 +769,33 @@ pub fn read_public_key_from(from: &KeyBytes, alg: &Option<Algorithm>) -> Result<
             read_pem_public_key(&str, alg)
                 .map_err(|e| ParseError("public key".to_string(), format!("{}", &e)))?
         }
+        (KeyBytes::FromEnv(KeyFormat::Hex, var), None) => read_env_string(var)?
+            .parse()
+            .map_err(|e| ParseError("public key".to_string(), format!("{}", &e)))?,
+        (KeyBytes::FromEnv(KeyFormat::Pem, var), None) => {
+            read_pem_public_key(&read_env_string(var)?, alg)
+                .map_err(|e| ParseError("public key".to_string(), format!("{}", &e)))?
+        }
+        (KeyBytes::FromEnv(KeyFormat::Raw, _), _) => Err(BinaryFromEnv)?,
+        (KeyBytes::FromFd(KeyFormat::Raw, fd), Some(alg)) => {
+            PublicKey::from_bytes(&read_fd_bytes(*fd)?, alg.0)
+                .map_err(|e| ParseError("public key".to_string(), format!("{}", &e)))?
+        }
+        (KeyBytes::FromFd(KeyFormat::Hex, fd), None) => read_fd_string(*fd)?
+            .parse()
+            .map_err(|e| ParseError("public key".to_string(), format!("{}", &e)))?,
+        (KeyBytes::FromFd(KeyFormat::Pem, fd), None) => {
+            read_pem_public_key(&read_fd_string(*fd)?, alg)
+                .map_err(|e| ParseError("public key".to_string(), format!("{}", &e)))?
+        }
         (KeyBytes::HexString(str), None) => str
             .parse()
             .map_err(|e| ParseError("public key".to_string(), format!("{}", &e)))?,
         (KeyBytes::PemString(str), None) => read_pem_public_key(str, alg)
             .map_err(|e| ParseError("public key".to_string(), format!("{}", &e)))?,
         (KeyBytes::FromStdin(KeyFormat::Raw), None)
-        | (KeyBytes::FromFile(KeyFormat::Raw, _), None) => {
+        | (KeyBytes::FromFile(KeyFormat::Raw, _), None)
+        | (KeyBytes::FromFd(KeyFormat::Raw, _), None) => {
             bail!("Raw public key binary input requires an explicit key algorithm")
         }
         (_, Some(_)) => {
@@ -446,102 +816,346 @@ pub fn read_public_key_from(from: &KeyBytes, alg: &Option<Algorithm>) -> Result<
     Ok(key)
 }
 
-pub fn read_biscuit_from(from: &BiscuitBytes) -> Result<UnverifiedBiscuit> {
-    let b = match from {
-        BiscuitBytes::FromStdin(BiscuitFormat::RawBiscuit) => {
-            UnverifiedBiscuit::from(read_stdin_bytes()?)?
+/// Reads the contents of a binary input (token, snapshot, third-party request or block), and
+/// decodes them to raw bytes
+pub fn read_bytes_from(from: &BiscuitBytes, desc: &str) -> Result<Vec<u8>> {
+    let (format, contents) = match from {
+        BiscuitBytes::FromStdin(format) if stdin_is_text(format, atty::is(Stream::Stdin)) => {
+            (format, read_stdin_string(desc)?.into_bytes())
         }
-        BiscuitBytes::FromStdin(BiscuitFormat::Base64Biscuit) => {
-            UnverifiedBiscuit::from_base64(read_stdin_string("base64-encoded biscuit")?)?
-        }
-        BiscuitBytes::FromFile(BiscuitFormat::RawBiscuit, path) => {
-            UnverifiedBiscuit::from(fs::read(path).map_err(|_| FileNotFound(path.clone()))?)?
+        BiscuitBytes::FromStdin(format) => (format, read_stdin_bytes()?),
+        BiscuitBytes::FromFile(format, path) => (
+            format,
+            fs::read(path).map_err(|_| FileNotFound(path.clone()))?,
+        ),
+        BiscuitBytes::FromEnv(BiscuitFormat::RawBiscuit, _) => Err(BinaryFromEnv)?,
+        BiscuitBytes::FromEnv(format, var) => (format, read_env_string(var)?.into_bytes()),
+        BiscuitBytes::FromFd(format, fd) => (format, read_fd_bytes(*fd)?),
+        BiscuitBytes::Base64String(str) => (&BiscuitFormat::AutoDetect, str.as_bytes().to_vec()),
+    };
+
+    decode_bytes(contents, format)
+}
+
+/// Whether stdin is read as text (with a prompt when it is a terminal) rather than as
+/// raw bytes. Raw inputs are never typed, so they are rejected when stdin is a terminal
+fn stdin_is_text(format: &BiscuitFormat, tty: bool) -> bool {
+    match format {
+        BiscuitFormat::Base64Biscuit | BiscuitFormat::Extracted(_) => true,
+        BiscuitFormat::AutoDetect => tty,
+        BiscuitFormat::RawBiscuit => false,
+    }
+}
+
+fn decode_bytes(contents: Vec<u8>, format: &BiscuitFormat) -> Result<Vec<u8>> {
+    match format {
+        BiscuitFormat::RawBiscuit => Ok(contents),
+        BiscuitFormat::Base64Biscuit => decode_text(
+            &String::from_utf8(contents)
+                .map_err(|_| ParseError("base64 input".to_string(), "invalid UTF-8".to_string()))?,
+        ),
+        // raw protobuf messages always contain non-printable bytes, while
+        // text encodings only contain printable characters and whitespace
+        BiscuitFormat::AutoDetect => {
+            if contents
+                .iter()
+                .all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+            {
+                decode_text(&String::from_utf8_lossy(&contents))
+            } else {
+                Ok(contents)
+            }
         }
-        BiscuitBytes::FromFile(BiscuitFormat::Base64Biscuit, path) => {
-            UnverifiedBiscuit::from_base64(
-                fs::read_to_string(path)
-                    .map_err(|_| FileNotFound(path.clone()))?
-                    .trim(),
-            )?
+        BiscuitFormat::Extracted(location) => decode_text(&extract_token(
+            &String::from_utf8(contents)
+                .map_err(|_| ParseError("HTTP input".to_string(), "invalid UTF-8".to_string()))?,
+            location,
+        )?),
+    }
+}
+
+/// Extracts a token from an HTTP request dump, a header line, a cookie string or a URL
+pub fn extract_token(text: &str, location: &TokenLocation) -> Result<String> {
+    let found = match location {
+        TokenLocation::HttpRequest {
+            header,
+            cookie,
+            query_param,
+        } => {
+            let mut lines = text.lines();
+            let request_line = lines.next().unwrap_or_default();
+            // headers stop at the first empty line, the body is ignored
+            let headers = lines
+                .take_while(|l| !l.trim().is_empty())
+                .collect::<Vec<_>>();
+            find_header(&headers, header)
+                .or_else(|| find_header(&headers, "Cookie").and_then(|c| find_cookie(&c, cookie)))
+                .or_else(|| {
+                    request_line
+                        .split_whitespace()
+                        .nth(1)
+                        .and_then(|target| find_query_param(target, query_param))
+                })
         }
-        BiscuitBytes::Base64String(str) => UnverifiedBiscuit::from_base64(str)?,
+        TokenLocation::Header(name) => find_header(&text.lines().collect::<Vec<_>>(), name),
+        TokenLocation::Cookie(name) => text.lines().find_map(|line| {
+            let line = line.trim();
+            let cookies = match line.split_once(':') {
+                Some((h, rest))
+                    if h.eq_ignore_ascii_case("cookie") || h.eq_ignore_ascii_case("set-cookie") =>
+                {
+                    rest
+                }
+                _ => line,
+            };
+            find_cookie(cookies, name)
+        }),
+        TokenLocation::Url(name) => find_query_param(text.trim(), name),
     };
-    Ok(b)
+
+    match found {
+        Some(token) if !token.is_empty() => Ok(token),
+        _ => Err(TokenNotFound(match location {
+            TokenLocation::HttpRequest { .. } => "the HTTP request".to_string(),
+            TokenLocation::Header(name) => format!("the `{}` header", name),
+            TokenLocation::Cookie(name) => format!("the `{}` cookie", name),
+            TokenLocation::Url(name) => format!("the `{}` query parameter", name),
+        }))?,
+    }
 }
 
-pub fn read_request_from(from: &BiscuitBytes) -> Result<ThirdPartyRequest> {
-    let req = match from {
-        BiscuitBytes::FromStdin(BiscuitFormat::RawBiscuit) => {
-            ThirdPartyRequest::deserialize(&read_stdin_bytes()?)?
-        }
-        BiscuitBytes::FromStdin(BiscuitFormat::Base64Biscuit) => {
-            ThirdPartyRequest::deserialize_base64(read_stdin_string(
-                "base64-encoded third-party block request",
-            )?)?
-        }
-        BiscuitBytes::FromFile(BiscuitFormat::RawBiscuit, path) => ThirdPartyRequest::deserialize(
-            &fs::read(path).map_err(|_| FileNotFound(path.clone()))?,
-        )?,
-        BiscuitBytes::FromFile(BiscuitFormat::Base64Biscuit, path) => {
-            ThirdPartyRequest::deserialize_base64(
-                fs::read_to_string(path)
-                    .map_err(|_| FileNotFound(path.clone()))?
-                    .trim(),
-            )?
-        }
-        BiscuitBytes::Base64String(str) => ThirdPartyRequest::deserialize_base64(str)?,
-    };
-    Ok(req)
+/// Finds a header value, and strips the `Bearer` authentication scheme if present. Values
+/// using another scheme (eg `Basic`) do not contain a biscuit, and are skipped
+fn find_header(lines: &[&str], name: &str) -> Option<String> {
+    lines.iter().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if !key.trim().eq_ignore_ascii_case(name) {
+            return None;
+        }
+        let value = value.trim();
+        let value = match value.split_once(' ') {
+            Some((scheme, credentials)) if scheme.eq_ignore_ascii_case("bearer") => {
+                credentials.trim()
+            }
+            Some((scheme, _)) if scheme.chars().all(|c| c.is_ascii_alphabetic()) => return None,
+            _ => value,
+        };
+        Some(value.to_owned())
+    })
 }
 
-pub fn read_snapshot_from(from: &BiscuitBytes) -> Result<Authorizer> {
-    let b = match from {
-        BiscuitBytes::FromStdin(BiscuitFormat::RawBiscuit) => {
-            Authorizer::from_raw_snapshot(&read_stdin_bytes()?)?
+fn find_cookie(cookies: &str, name: &str) -> Option<String> {
+    cookies.split(';').find_map(|cookie| {
+        let (key, value) = cookie.split_once('=')?;
+        if key.trim() == name {
+            Some(value.trim().trim_matches('"').to_owned())
+        } else {
+            None
         }
-        BiscuitBytes::FromStdin(BiscuitFormat::Base64Biscuit) => {
-            Authorizer::from_base64_snapshot(&read_stdin_string("base64-encoded biscuit")?)?
+    })
+}
+
+fn find_query_param(url: &str, name: &str) -> Option<String> {
+    let (_, query) = url.split_once('?')?;
+    let query = query.split('#').next().unwrap_or_default();
+    query.split('&').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        if percent_decode(key) == name {
+            Some(percent_decode(value))
+        } else {
+            None
         }
-        BiscuitBytes::FromFile(BiscuitFormat::RawBiscuit, path) => {
-            Authorizer::from_raw_snapshot(&fs::read(path).map_err(|_| FileNotFound(path.clone()))?)?
+    })
+}
+
+fn percent_decode(str: &str) -> String {
+    let bytes = str.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) => match hex::decode(hex) {
+                Ok(b) => {
+                    decoded.extend(b);
+                    i += 3;
+                }
+                Err(_) => {
+                    decoded.push(b'%');
+                    i += 1;
+                }
+            },
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
         }
-        BiscuitBytes::FromFile(BiscuitFormat::Base64Biscuit, path) => {
-            Authorizer::from_base64_snapshot(
-                fs::read_to_string(path)
-                    .map_err(|_| FileNotFound(path.clone()))?
-                    .trim(),
-            )?
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Decodes base64 text, with the URL-safe or standard alphabet, with or without padding.
+/// The text can be prefixed with `biscuit:` or wrapped in PEM-style armor.
+pub fn decode_text(str: &str) -> Result<Vec<u8>> {
+    let str = str.trim();
+    let str = strip_armor(str).unwrap_or(str);
+    let str = str.strip_prefix("biscuit:").unwrap_or(str);
+    let compact = str
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect::<String>();
+
+    let configs = [
+        base64::URL_SAFE,
+        base64::URL_SAFE_NO_PAD,
+        base64::STANDARD,
+        base64::STANDARD_NO_PAD,
+    ];
+    let mut error = None;
+    for config in configs {
+        match base64::decode_config(&compact, config) {
+            Ok(bytes) => return Ok(bytes),
+            Err(e) => {
+                error.get_or_insert(e);
+            }
         }
-        BiscuitBytes::Base64String(str) => Authorizer::from_base64_snapshot(str)?,
+    }
+    Err(ParseError(
+        "base64 input".to_string(),
+        error.map(|e| e.to_string()).unwrap_or_default(),
+    ))?
+}
+
+/// Extracts the body of a `-----BEGIN …-----` / `-----END …-----` block, skipping
+/// `Key: value` headers
+fn strip_armor(str: &str) -> Option<&str> {
+    let body = str.strip_prefix("-----BEGIN ")?;
+    let (_, body) = body.split_once("-----")?;
+    let (body, _) = body.split_once("-----END ")?;
+    let body = match body.rsplit_once(':') {
+        Some((_, rest)) => rest.split_once('\n').map(|(_, b)| b).unwrap_or(""),
+        None => body,
     };
-    Ok(b)
+    Some(body)
+}
+
+pub fn read_biscuit_from(from: &BiscuitBytes) -> Result<UnverifiedBiscuit> {
+    let bytes = read_bytes_from(from, "base64-encoded biscuit")?;
+    Ok(UnverifiedBiscuit::from(bytes)?)
+}
+
+pub fn read_request_from(from: &BiscuitBytes) -> Result<ThirdPartyRequest> {
+    let bytes = read_bytes_from(from, "base64-encoded third-party block request")?;
+    Ok(ThirdPartyRequest::deserialize(&bytes)?)
+}
+
+/// The signature of the block a third-party block request was generated from
+pub fn request_previous_signature(request: &ThirdPartyRequest) -> Result<Vec<u8>> {
+    Ok(decode_request(request)?.previous_signature)
+}
+
+/// Only legacy (v0) requests carry the public key of the previous block
+pub fn request_previous_key(request: &ThirdPartyRequest) -> Result<Option<PublicKey>> {
+    decode_request(request)?
+        .legacy_previous_key
+        .as_ref()
+        .map(proto_public_key)
+        .transpose()
+}
+
+fn decode_request(request: &ThirdPartyRequest) -> Result<schema::ThirdPartyBlockRequest> {
+    Ok(
+        schema::ThirdPartyBlockRequest::decode(&request.serialize()?[..])
+            .map_err(|e| ParseError("third-party block request".to_string(), e.to_string()))?,
+    )
+}
+
+/// The public key each block carries, used to sign the next block
+pub fn block_next_keys(biscuit: &UnverifiedBiscuit) -> Result<Vec<PublicKey>> {
+    let token = schema::Biscuit::decode(&biscuit.to_vec()?[..])
+        .map_err(|e| ParseError("biscuit".to_string(), e.to_string()))?;
+    std::iter::once(&token.authority)
+        .chain(token.blocks.iter())
+        .map(|block| proto_public_key(&block.next_key))
+        .collect()
+}
+
+pub fn read_snapshot_from(from: &BiscuitBytes) -> Result<Authorizer> {
+    let bytes = read_bytes_from(from, "base64-encoded snapshot")?;
+    Ok(Authorizer::from_raw_snapshot(&bytes)?)
 }
 
 pub fn append_third_party_from(
     biscuit: &UnverifiedBiscuit,
     from: &BiscuitBytes,
 ) -> Result<UnverifiedBiscuit> {
-    let b = match from {
-        BiscuitBytes::FromStdin(BiscuitFormat::RawBiscuit) => {
-            biscuit.append_third_party(&read_stdin_bytes()?)?
-        }
-        BiscuitBytes::FromStdin(BiscuitFormat::Base64Biscuit) => biscuit
-            .append_third_party_base64(read_stdin_string("base64-encode third-party block")?)?,
-        BiscuitBytes::FromFile(BiscuitFormat::RawBiscuit, path) => {
-            biscuit.append_third_party(&fs::read(path).map_err(|_| FileNotFound(path.clone()))?)?
-        }
-        BiscuitBytes::FromFile(BiscuitFormat::Base64Biscuit, path) => biscuit
-            .append_third_party_base64(
-                fs::read_to_string(path)
-                    .map_err(|_| FileNotFound(path.clone()))?
-                    .trim(),
-            )?,
-        BiscuitBytes::Base64String(str) => biscuit.append_third_party_base64(str)?,
+    let bytes = read_bytes_from(from, "base64-encoded third-party block")?;
+    Ok(biscuit.append_third_party(&bytes)?)
+}
+
+/// Quotes a string as a datalog string literal
+fn datalog_string(str: &str) -> String {
+    format!(
+        "\"{}\"",
+        str.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+/// Checks restricting a token to the given operations, resource prefixes and client IP addresses.
+/// They rely on `operation`, `resource` and `client_ip_address` facts provided by the authorizer
+pub fn restriction_checks(operations: &[String], resources: &[String], ips: &[IpAddr]) -> String {
+    let one_of = |name: &str, values: Vec<String>| match values.as_slice() {
+        [value] => format!("check if {}({});\n", name, value),
+        _ => format!(
+            "check if {}(${}), {{{}}}.contains(${});\n",
+            name,
+            name,
+            values.join(", "),
+            name
+        ),
     };
-    Ok(b)
+
+    let mut code = String::new();
+    if !operations.is_empty() {
+        code.push_str(&one_of(
+            "operation",
+            operations.iter().map(|o| datalog_string(o)).collect(),
+        ));
+    }
+    if !resources.is_empty() {
+        let prefixes = resources
+            .iter()
+            .map(|r| {
+                format!(
+                    "resource($resource), $resource.starts_with({})",
+                    datalog_string(r)
+                )
+            })
+            .collect::<Vec<_>>();
+        code.push_str(&format!("check if {};\n", prefixes.join(" or ")));
+    }
+    if !ips.is_empty() {
+        code.push_str(&one_of(
+            "client_ip_address",
+            ips.iter()
+                .map(|ip| datalog_string(&ip.to_string()))
+                .collect(),
+        ));
+    }
+    code
+}
+
+/// Reads a preset from `dir/name.datalog`
+pub fn read_preset(dir: &Path, name: &str) -> Result<String> {
+    if name.contains(['/', '\\']) || name.starts_with('.') {
+        bail!("Invalid preset name: {}", name);
+    }
+    let path = dir.join(format!("{}.datalog", name));
+    fs::read_to_string(&path).map_err(|_| UnknownPreset(name.to_string(), path).into())
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Ttl {
     Duration(Duration),
     DateTime(DateTime<Utc>),
@@ -581,47 +1195,66 @@ pub enum Param {
 }
 
 pub fn parse_param(kv: &str) -> Result<Param, std::io::Error> {
-    use std::io::{Error, ErrorKind};
-    let (binding, value) = (kv.split_once('=').ok_or_else(|| Error::new(
-        ErrorKind::Other,
-        "Params must be `key=value` or `key:type=value` where type is pubkey, string, integer, date, bytes or bool.",
+    use std::io::Error;
+    let (binding, value) = (kv.split_once('=').ok_or_else(|| Error::other(
+        "Params must be `key=value` or `key:type=value` where type is pubkey, string, integer, date, bytes, bool or set.",
     )))?;
 
-    let (name, annotation) = match binding.rsplit_once(':') {
+    let (name, annotation) = split_param_binding(binding);
+    parse_param_value(name, annotation, value)
+}
+
+fn split_param_binding(binding: &str) -> (&str, Option<&str>) {
+    match binding.rsplit_once(':') {
         None => (binding, None),
         Some((name, annotation)) => (name, Some(annotation)),
-    };
+    }
+}
+
+/// `set` and `set<type>` annotations. The inner option contains the element type, if any
+pub fn set_element_annotation(annotation: &str) -> Option<Option<&str>> {
+    if annotation == "set" {
+        Some(None)
+    } else {
+        annotation
+            .strip_prefix("set<")
+            .and_then(|a| a.strip_suffix('>'))
+            .map(Some)
+    }
+}
 
+pub fn parse_param_value(
+    name: &str,
+    annotation: Option<&str>,
+    value: &str,
+) -> Result<Param, std::io::Error> {
+    use std::io::Error;
     match annotation {
       Some("pubkey") => {
         let pubkey = value.parse().map_err(Error::other)?;
         Ok(Param::PublicKey(name.to_string(), pubkey))
       },
       Some("integer") => {
-        let int = value
-            .parse()
-            .map_err(|e| Error::new(ErrorKind::Other, format!("{}", &e)))?;
+        let int = value.parse().map_err(|e| Error::other(format!("{}", &e)))?;
         Ok(Param::Term(name.to_string(), Term::Integer(int)))
       },
       Some("date") => {
         let date =
             time::OffsetDateTime::parse(value, &time::format_description::well_known::Rfc3339)
-                .map_err(|e| Error::new(ErrorKind::Other, format!("{}", &e)))?;
+                .map_err(|e| Error::other(format!("{}", &e)))?;
         let timestamp = date
             .unix_timestamp()
             .try_into()
-            .map_err(|e| Error::new(ErrorKind::Other, format!("{}", &e)))?;
+            .map_err(|e| Error::other(format!("{}", &e)))?;
         Ok(Param::Term(name.to_string(), Term::Date(timestamp)))
       },
       Some("bytes") => {
         let hex_bytes = value.strip_prefix("hex:").ok_or_else(|| {
-            Error::new(
-        ErrorKind::Other,
+            Error::other(
         "Unusupported byte array literal. Byte arrays must be hex-encoded and start with `hex:`."
         )
         })?;
-        let bytes =
-            hex::decode(hex_bytes).map_err(|e| Error::new(ErrorKind::Other, format!("{}", &e)))?;
+        let bytes = hex::decode(hex_bytes).map_err(|e| Error::other(format!("{}", &e)))?;
         Ok(Param::Term(name.to_string(), Term::Bytes(bytes)))
       },
       Some("bool") => {
@@ -630,8 +1263,7 @@ pub fn parse_param(kv: &str) -> Result<Param, std::io::Error> {
         } else if value.to_lowercase() == "false" {
             Ok(Param::Term(name.to_string(), Term::Bool(false)))
         } else {
-            Err(Error::new(
-                ErrorKind::Other,
+            Err(Error::other(
                 "Boolean params must be either \"true\" or \"false\".",
             ))
         }
@@ -639,17 +1271,640 @@ pub fn parse_param(kv: &str) -> Result<Param, std::io::Error> {
       Some("string") | None => {
         Ok(Param::Term(name.to_string(), Term::Str(value.to_string())))
       },
+      Some(set) if set_element_annotation(set).is_some() => {
+        let element_annotation = set_element_annotation(set).flatten();
+        let mut terms = BTreeSet::new();
+        for element in value.split(',').filter(|e| !e.is_empty()) {
+            match parse_param_value(name, element_annotation, element)? {
+                Param::Term(_, Term::Set(_)) => Err(Error::other("Sets cannot be nested."))?,
+                Param::Term(_, t) => terms.insert(t),
+                Param::PublicKey(_, _) => Err(Error::other("Sets cannot contain public keys."))?,
+            };
+        }
+        Ok(Param::Term(name.to_string(), Term::Set(terms)))
+      },
       _ => {
-        Err(Error::new(
-                ErrorKind::Other,
-                "Unsupported parameter type. Supported types are `pubkey`, `string`, `integer`, `date`, `bytes`, or `bool`.",
+        Err(Error::other(
+                "Unsupported parameter type. Supported types are `pubkey`, `string`, `integer`, `date`, `bytes`, `bool`, `set` or `set<type>`.",
             ))
       }
     }
 }
 
+/// Reads datalog parameters from a JSON or TOML file (depending on the file extension).
+///
+/// The file contains a map from parameter names to values. Strings, integers, booleans and arrays
+/// (as sets) are mapped to the corresponding datalog terms, TOML dates are mapped to datalog dates.
+/// Other types can be provided as strings, with a type annotation on the parameter name, as with
+/// `--param` (eg `"expiration:date": "2025-01-01T00:00:00Z"`).
+pub fn read_params_file(path: &Path) -> Result<Vec<Param>> {
+    let contents = fs::read_to_string(path).map_err(|_| FileNotFound(path.to_path_buf()))?;
+    let is_toml = path.extension().map(|e| e == "toml").unwrap_or(false);
+
+    let values: Vec<(String, ParamValue)> = if is_toml {
+        let table: toml::value::Table = toml::from_str(&contents)
+            .map_err(|e| ParseError("params file".to_string(), e.to_string()))?;
+        table
+            .into_iter()
+            .map(|(k, v)| Ok((k, ParamValue::from_toml(v)?)))
+            .collect::<Result<_>>()?
+    } else {
+        let map: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&contents)
+            .map_err(|e| ParseError("params file".to_string(), e.to_string()))?;
+        map.into_iter()
+            .map(|(k, v)| Ok((k, ParamValue::from_json(v)?)))
+            .collect::<Result<_>>()?
+    };
+
+    values
+        .into_iter()
+        .map(|(binding, value)| {
+            let (name, annotation) = split_param_binding(&binding);
+            value
+                .into_param(name, annotation)
+                .map_err(|e| ParseError(format!("param `{}`", name), e.to_string()).into())
+        })
+        .collect()
+}
+
+/// Parameter values from a params file, before they are converted to datalog terms
+enum ParamValue {
+    Str(String),
+    Integer(i64),
+    Bool(bool),
+    Date(String),
+    Array(Vec<ParamValue>),
+}
+
+impl ParamValue {
+    fn from_json(value: serde_json::Value) -> Result<Self> {
+        use serde_json::Value;
+        Ok(match value {
+            Value::String(s) => Self::Str(s),
+            Value::Bool(b) => Self::Bool(b),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => Self::Integer(i),
+                None => bail!("only integers are supported in params files, found {}", n),
+            },
+            Value::Array(a) => {
+                Self::Array(a.into_iter().map(Self::from_json).collect::<Result<_>>()?)
+            }
+            Value::Null => bail!("null values are not supported in params files"),
+            Value::Object(_) => bail!("objects are not supported in params files"),
+        })
+    }
+
+    fn from_toml(value: toml::Value) -> Result<Self> {
+        use toml::Value;
+        Ok(match value {
+            Value::String(s) => Self::Str(s),
+            Value::Boolean(b) => Self::Bool(b),
+            Value::Integer(i) => Self::Integer(i),
+            Value::Datetime(d) => Self::Date(d.to_string()),
+            Value::Array(a) => {
+                Self::Array(a.into_iter().map(Self::from_toml).collect::<Result<_>>()?)
+            }
+            Value::Float(_) => bail!("floats are not supported in params files"),
+            Value::Table(_) => bail!("tables are not supported in params files"),
+        })
+    }
+
+    fn into_param(self, name: &str, annotation: Option<&str>) -> Result<Param> {
+        let term = match (self, annotation) {
+            (Self::Str(s), _) => return Ok(parse_param_value(name, annotation, &s)?),
+            (Self::Integer(i), None | Some("integer")) => Term::Integer(i),
+            (Self::Bool(b), None | Some("bool")) => Term::Bool(b),
+            (Self::Date(d), None | Some("date")) => {
+                return Ok(parse_param_value(name, Some("date"), &d)?)
+            }
+            (Self::Array(values), _) => {
+                let element_annotation = match annotation.map(|a| (a, set_element_annotation(a))) {
+                    Some((_, Some(element_annotation))) => element_annotation,
+                    Some((a, None)) => Some(a),
+                    None => None,
+                };
+                let mut set = BTreeSet::new();
+                for v in values {
+                    match v.into_param(name, element_annotation)? {
+                        Param::Term(_, Term::Set(_)) => bail!("sets cannot be nested"),
+                        Param::Term(_, t) => set.insert(t),
+                        Param::PublicKey(_, _) => bail!("sets cannot contain public keys"),
+                    };
+                }
+                Term::Set(set)
+            }
+            (_, Some(a)) => bail!("value does not match the `{}` type annotation", a),
+        };
+        Ok(Param::Term(name.to_string(), term))
+    }
+}
+
+/// Names of the parameters referenced by datalog code, split between
+/// term parameters (`{name}`) and scope parameters (`trusting {name}`)
+#[derive(Debug, Default)]
+pub struct ParamNames {
+    pub terms: BTreeSet<String>,
+    pub scopes: BTreeSet<String>,
+}
+
+impl ParamNames {
+    /// Returns `None` if the code does not parse: the error is reported when building the block
+    pub fn from_source(source: &str) -> Option<Self> {
+        // block-level `trusting` annotations are only accepted by the block parser
+        let result = biscuit_auth::parser::parse_source(source)
+            .or_else(|_| biscuit_auth::parser::parse_block_source(source))
+            .ok()?;
+        let mut names = Self::default();
+        for scope in result.scopes {
+            names.add_scope(&scope.into());
+        }
+        for (_, fact) in result.facts {
+            let fact: Fact = fact.into();
+            names.add_terms(&fact.parameters);
+        }
+        for (_, rule) in result.rules {
+            names.add_rule(&rule.into());
+        }
+        for (_, check) in result.checks {
+            let check: Check = check.into();
+            check.queries.iter().for_each(|q| names.add_rule(q));
+        }
+        for (_, policy) in result.policies {
+            let policy: Policy = policy.into();
+            policy.queries.iter().for_each(|q| names.add_rule(q));
+        }
+        Some(names)
+    }
+
+    pub fn from_rule(rule: &Rule) -> Self {
+        let mut names = Self::default();
+        names.add_rule(rule);
+        names
+    }
+
+    pub fn extend(&mut self, other: Self) {
+        self.terms.extend(other.terms);
+        self.scopes.extend(other.scopes);
+    }
+
+    fn add_rule(&mut self, rule: &Rule) {
+        self.add_terms(&rule.parameters);
+        if let Some(scopes) = &rule.scope_parameters {
+            self.scopes.extend(scopes.keys().cloned());
+        }
+        rule.scopes.iter().for_each(|s| self.add_scope(s));
+    }
+
+    fn add_terms<T>(&mut self, parameters: &Option<HashMap<String, T>>) {
+        if let Some(parameters) = parameters {
+            self.terms.extend(parameters.keys().cloned());
+        }
+    }
+
+    fn add_scope(&mut self, scope: &Scope) {
+        if let Scope::Parameter(name) = scope {
+            self.scopes.insert(name.clone());
+        }
+    }
+
+    fn uses(&self, param: &Param) -> bool {
+        match param {
+            Param::Term(name, _) => self.terms.contains(name),
+            Param::PublicKey(name, _) => self.scopes.contains(name),
+        }
+    }
+
+    /// Fails with the list of referenced parameters which have no value of the expected kind.
+    /// Term parameters can hold any type, so only scope parameters are annotated (`pubkey`),
+    /// and the error lists the forms term parameters can be provided with
+    pub fn ensure_provided(&self, params: &[Param]) -> Result<()> {
+        let has_term = |n: &String| {
+            params
+                .iter()
+                .any(|p| matches!(p, Param::Term(name, _) if name == n))
+        };
+        let has_key = |n: &String| {
+            params
+                .iter()
+                .any(|p| matches!(p, Param::PublicKey(name, _) if name == n))
+        };
+        let missing_terms = self
+            .terms
+            .iter()
+            .filter(|n| !has_term(n))
+            .cloned()
+            .collect::<Vec<_>>();
+        let missing = missing_terms
+            .iter()
+            .cloned()
+            .chain(
+                self.scopes
+                    .iter()
+                    .filter(|n| !has_key(n))
+                    .map(|n| format!("{} (pubkey)", n)),
+            )
+            .collect::<Vec<_>>();
+        match (missing.is_empty(), missing_terms.is_empty()) {
+            (true, _) => Ok(()),
+            (false, true) => Err(MissingParams(missing.join(", ")))?,
+            (false, false) => Err(MissingTermParams(missing.join(", ")))?,
+        }
+    }
+}
+
+/// Reports the provided parameters that are not referenced by any datalog code,
+/// as an error in strict mode and as a warning otherwise
+pub fn ensure_params_used(params: &[Param], referenced: &ParamNames, strict: bool) -> Result<()> {
+    let unused = params
+        .iter()
+        .filter(|p| !referenced.uses(p))
+        .map(|p| match p {
+            Param::Term(name, t) => format!("{} ({})", name, term_type(t)),
+            Param::PublicKey(name, _) => format!("{} (pubkey)", name),
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    if unused.is_empty() {
+        Ok(())
+    } else if strict {
+        Err(UnusedParams(unused.join(", ")))?
+    } else {
+        eprintln!("Warning: unused datalog parameters: {}", unused.join(", "));
+        Ok(())
+    }
+}
+
+pub fn term_type(term: &Term) -> &'static str {
+    match term {
+        Term::Integer(_) => "integer",
+        Term::Str(_) => "string",
+        Term::Date(_) => "date",
+        Term::Bytes(_) => "bytes",
+        Term::Bool(_) => "bool",
+        Term::Set(_) => "set",
+        Term::Null => "null",
+        Term::Array(_) => "array",
+        Term::Map(_) => "map",
+        Term::Variable(_) | Term::Parameter(_) => "term",
+    }
+}
+
+/// Converts a TOML value (as found in params files and templates) to a datalog parameter
+pub fn param_from_toml(name: &str, annotation: Option<&str>, value: toml::Value) -> Result<Param> {
+    ParamValue::from_toml(value)?.into_param(name, annotation)
+}
+
+/// The public key (or its fingerprint) a third-party block is expected to be signed with
+#[derive(Debug, Clone)]
+pub struct ExpectedExternalKey {
+    pub block: usize,
+    pub key: KeyPattern,
+}
+
+pub fn parse_expected_external_key(kv: &str) -> Result<ExpectedExternalKey, std::io::Error> {
+    use std::io::Error;
+    let (block, key) = kv.split_once('=').ok_or_else(|| {
+        Error::other("Expected external keys must be `index=pubkey` or `index=fingerprint`")
+    })?;
+    let block = block
+        .trim()
+        .parse()
+        .map_err(|_| Error::other(format!("Invalid block index: {}", block)))?;
+    let key = key
+        .trim()
+        .parse()
+        .map_err(|e| Error::other(format!("Invalid public key or fingerprint: {}", e)))?;
+    Ok(ExpectedExternalKey { block, key })
+}
+
+/// Reads a list of public keys or fingerprints, one per line. Empty lines, and
+/// everything after a `#`, are ignored
+pub fn read_keyring(path: &Path) -> Result<Vec<KeyPattern>> {
+    let contents = fs::read_to_string(path).map_err(|_| FileNotFound(path.to_path_buf()))?;
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.parse::<KeyPattern>()
+                .map_err(|e| ParseError(format!("public key `{}`", line), e).into())
+        })
+        .collect()
+}
+
 pub fn parse_rule(rule: &str) -> Result<Rule, std::io::Error> {
-    use std::io::{Error, ErrorKind};
+    use std::io::Error;
     rule.try_into()
-        .map_err(|e| Error::new(ErrorKind::Other, format!("Could not parse rule: {e}")))
+        .map_err(|e| Error::other(format!("Could not parse rule: {e}")))
+}
+
+#[test]
+fn decode_text_encodings() {
+    let bytes = vec![0xfb, 0xff, 0x01, 0x02];
+    for encoded in [
+        "-_8BAg==",
+        "-_8BAg",
+        "+/8BAg==",
+        "+/8BAg",
+        "biscuit:-_8BAg==",
+        "-----BEGIN BISCUIT TOKEN-----\nRoot-Key-Id: 1\n\n+/8B\nAg==\n-----END BISCUIT TOKEN-----\n",
+    ] {
+        assert_eq!(decode_text(encoded).unwrap(), bytes, "{}", encoded);
+    }
+}
+
+#[test]
+fn stdin_encodings() {
+    // raw input can't be typed in a terminal, it must be piped
+    assert!(!stdin_is_text(&BiscuitFormat::RawBiscuit, true));
+    assert!(!stdin_is_text(&BiscuitFormat::RawBiscuit, false));
+    assert!(stdin_is_text(&BiscuitFormat::AutoDetect, true));
+    assert!(!stdin_is_text(&BiscuitFormat::AutoDetect, false));
+    assert!(stdin_is_text(&BiscuitFormat::Base64Biscuit, true));
+    assert!(stdin_is_text(&BiscuitFormat::Base64Biscuit, false));
+}
+
+#[test]
+fn extract_token_locations() {
+    let request = "GET /a?token=url HTTP/1.1\r\nHost: example.com\r\nCookie: a=b; biscuit=cookie\r\nAuthorization: Bearer header\r\n\r\nbody";
+    let http = |header: &str, cookie: &str| TokenLocation::HttpRequest {
+        header: header.to_owned(),
+        cookie: cookie.to_owned(),
+        query_param: "token".to_owned(),
+    };
+    assert_eq!(
+        extract_token(request, &http("Authorization", "biscuit")).unwrap(),
+        "header"
+    );
+    assert_eq!(
+        extract_token(request, &http("X-Biscuit", "biscuit")).unwrap(),
+        "cookie"
+    );
+    assert_eq!(
+        extract_token(request, &http("X-Biscuit", "other")).unwrap(),
+        "url"
+    );
+    let basic = request.replace("Bearer header", "Basic dXNlcjpwYXNz");
+    assert_eq!(
+        extract_token(&basic, &http("Authorization", "biscuit")).unwrap(),
+        "cookie"
+    );
+    assert!(extract_token(
+        "Authorization: Basic dXNlcjpwYXNz",
+        &TokenLocation::Header("Authorization".to_owned())
+    )
+    .is_err());
+    assert_eq!(
+        extract_token(
+            "x-biscuit: abc",
+            &TokenLocation::Header("X-Biscuit".to_owned())
+        )
+        .unwrap(),
+        "abc"
+    );
+    assert_eq!(
+        extract_token(
+            "Set-Cookie: biscuit=\"abc\"; Path=/",
+            &TokenLocation::Cookie("biscuit".to_owned())
+        )
+        .unwrap(),
+        "abc"
+    );
+    assert_eq!(
+        extract_token(
+            "https://example.com/?t=abc%3D%3D#x",
+            &TokenLocation::Url("t".to_owned())
+        )
+        .unwrap(),
+        "abc=="
+    );
+    assert!(extract_token("https://example.com/", &TokenLocation::Url("t".to_owned())).is_err());
+}
+
+#[test]
+fn params_file_values() {
+    let mut file = tempfile::Builder::new().suffix(".json").tempfile().unwrap();
+    std::io::Write::write_all(
+        &mut file,
+        br#"{"name": "john", "age": 42, "ops": ["read", "write"], "exp:date": "2025-01-01T00:00:00Z"}"#,
+    )
+    .unwrap();
+    let params = read_params_file(file.path()).unwrap();
+    let terms = params
+        .into_iter()
+        .map(|p| match p {
+            Param::Term(name, t) => (name, t),
+            Param::PublicKey(name, _) => panic!("unexpected public key {}", name),
+        })
+        .collect::<HashMap<_, _>>();
+    assert_eq!(terms["name"], Term::Str("john".to_string()));
+    assert_eq!(terms["age"], Term::Integer(42));
+    assert_eq!(terms["exp"], Term::Date(1735689600));
+    assert_eq!(
+        terms["ops"],
+        Term::Set(BTreeSet::from([
+            Term::Str("read".to_string()),
+            Term::Str("write".to_string())
+        ]))
+    );
+}
+
+#[test]
+fn parse_set_params() {
+    let terms = |kv: &str| match parse_param(kv).unwrap() {
+        Param::Term(_, Term::Set(terms)) => terms,
+        _ => panic!("expected a set"),
+    };
+    assert_eq!(
+        terms("ops:set=read,write"),
+        BTreeSet::from([
+            Term::Str("read".to_string()),
+            Term::Str("write".to_string())
+        ])
+    );
+    assert_eq!(
+        terms("ids:set<integer>=1,2"),
+        BTreeSet::from([Term::Integer(1), Term::Integer(2)])
+    );
+    assert_eq!(terms("empty:set="), BTreeSet::new());
+    assert!(parse_param("keys:set<pubkey>=ed25519/00").is_err());
+}
+
+#[test]
+fn missing_and_unused_params() {
+    let names =
+        ParamNames::from_source("user({user}); check if right(\"read\") trusting {service};")
+            .unwrap();
+    assert_eq!(names.terms.iter().collect::<Vec<_>>(), vec!["user"]);
+    assert_eq!(names.scopes.iter().collect::<Vec<_>>(), vec!["service"]);
+
+    let user = parse_param("user=alice").unwrap();
+    let age = parse_param("age:integer=42").unwrap();
+    assert_eq!(
+        names
+            .ensure_provided(std::slice::from_ref(&user))
+            .unwrap_err()
+            .to_string(),
+        "Missing datalog parameters: service (pubkey)"
+    );
+    assert_eq!(
+        names.ensure_provided(&[]).unwrap_err().to_string(),
+        "Missing datalog parameters: user, service (pubkey). Term parameters are provided with `--param name=value` for a string, or `--param name:type=value` where type is integer, date, bytes, bool, set or set<type>"
+    );
+    assert!(ensure_params_used(&[user.clone(), age.clone()], &names, false).is_ok());
+    assert_eq!(
+        ensure_params_used(&[user, age], &names, true)
+            .unwrap_err()
+            .to_string(),
+        "Unused datalog parameters: age (integer)"
+    );
+}
+
+#[test]
+fn restriction_checks_parse() {
+    let code = restriction_checks(
+        &["read".to_string(), "write".to_string()],
+        &["/docs/".to_string(), "/a\"b".to_string()],
+        &["127.0.0.1".parse().unwrap()],
+    );
+    assert_eq!(
+        code,
+        "check if operation($operation), {\"read\", \"write\"}.contains($operation);\n\
+         check if resource($resource), $resource.starts_with(\"/docs/\") or resource($resource), $resource.starts_with(\"/a\\\"b\");\n\
+         check if client_ip_address(\"127.0.0.1\");\n"
+    );
+    assert!(BlockBuilder::new().code(&code).is_ok());
+}
+
+#[test]
+fn expected_external_keys() {
+    let key = "ed25519/07955ee5c68dbcf614142ddcecea962b91cd69d477f0ad0f8a15f28eba264f67";
+    let expected = parse_expected_external_key(&format!("1={}", key)).unwrap();
+    assert_eq!(expected.block, 1);
+    assert_eq!(expected.key.to_string(), key);
+    let fingerprint = "ed25519:sha256:-COqoRd_uqi-4cAYvDkmlg";
+    let expected = parse_expected_external_key(&format!("2={}", fingerprint)).unwrap();
+    assert_eq!(expected.key.to_string(), fingerprint);
+    assert!(parse_expected_external_key(key).is_err());
+    assert!(parse_expected_external_key("a=ed25519/1234").is_err());
+}
+
+#[test]
+fn block_level_trusting_is_kept() {
+    use biscuit_auth::{Biscuit, KeyPair};
+
+    let root = KeyPair::new();
+    let external = KeyPair::new();
+    let code = format!(
+        "trusting authority, {}; check if group(\"admin\");",
+        external.public()
+    );
+    let mut referenced = ParamNames::default();
+    let builder = read_authority_from(
+        &DatalogInput::DatalogString(code.clone()),
+        &[],
+        &mut referenced,
+        &None,
+        Biscuit::builder(),
+    )
+    .unwrap();
+    let biscuit = builder.build(&root).unwrap();
+    let block = read_block_from(
+        &DatalogInput::DatalogString(code),
+        &[],
+        &mut referenced,
+        &None,
+        BlockBuilder::new(),
+    )
+    .unwrap();
+    let biscuit = biscuit.append(block).unwrap();
+
+    let expected = vec![Scope::Authority, Scope::PublicKey(external.public())];
+    assert_eq!(
+        block_scopes(&biscuit.to_vec().unwrap()).unwrap(),
+        vec![expected.clone(), expected]
+    );
+}
+
+#[test]
+fn file_descriptor_paths() {
+    assert_eq!(fd_number(Path::new("fd:3")), Some(3));
+    assert_eq!(fd_number(Path::new("fd:0")), Some(0));
+    assert_eq!(fd_number(Path::new("fd:")), None);
+    assert_eq!(fd_number(Path::new("fd:-1")), None);
+    assert_eq!(fd_number(Path::new("fd:three")), None);
+    assert_eq!(fd_number(Path::new("./fd:3")), None);
+
+    let biscuit = |path: &str| biscuit_bytes_from_path(Path::new(path), BiscuitFormat::AutoDetect);
+    assert!(matches!(biscuit("-"), BiscuitBytes::FromStdin(_)));
+    assert!(matches!(biscuit("fd:0"), BiscuitBytes::FromStdin(_)));
+    assert!(matches!(biscuit("fd:3"), BiscuitBytes::FromFd(_, 3)));
+    assert!(matches!(biscuit("fd:three"), BiscuitBytes::FromFile(_, _)));
+
+    let key = |path: &str| key_bytes_from_path(Path::new(path), KeyFormat::Hex);
+    assert!(matches!(key("fd:0"), KeyBytes::FromStdin(KeyFormat::Hex)));
+    assert!(matches!(key("fd:4"), KeyBytes::FromFd(KeyFormat::Hex, 4)));
+    assert!(matches!(key("key-file"), KeyBytes::FromFile(_, _)));
+}
+
+#[test]
+fn environment_variable_inputs() {
+    assert_eq!(
+        env_var_name(Path::new("env:BISCUIT_TOKEN")),
+        Some("BISCUIT_TOKEN".to_string())
+    );
+    assert_eq!(env_var_name(Path::new("BISCUIT_TOKEN")), None);
+    assert_eq!(env_var_name(Path::new("./env:BISCUIT_TOKEN")), None);
+
+    env::set_var("BISCUIT_CLI_TEST_ENV_STRING", "  ed25519/abcd\n");
+    assert_eq!(
+        read_env_string("BISCUIT_CLI_TEST_ENV_STRING").unwrap(),
+        "ed25519/abcd"
+    );
+    let missing = read_env_string("BISCUIT_CLI_TEST_ENV_MISSING").unwrap_err();
+    assert!(matches!(
+        missing.downcast_ref::<crate::errors::CliError>(),
+        Some(EnvVarNotFound(var)) if var == "BISCUIT_CLI_TEST_ENV_MISSING"
+    ));
+
+    let raw = BiscuitBytes::FromEnv(
+        BiscuitFormat::RawBiscuit,
+        "BISCUIT_CLI_TEST_ENV_STRING".to_string(),
+    );
+    assert!(matches!(
+        read_biscuit_from(&raw)
+            .unwrap_err()
+            .downcast_ref::<crate::errors::CliError>(),
+        Some(BinaryFromEnv)
+    ));
+    let raw_key = KeyBytes::FromEnv(KeyFormat::Raw, "BISCUIT_CLI_TEST_ENV_STRING".to_string());
+    assert!(matches!(
+        read_public_key_from(&raw_key, &None)
+            .unwrap_err()
+            .downcast_ref::<crate::errors::CliError>(),
+        Some(BinaryFromEnv)
+    ));
+}
+
+#[test]
+fn authorizer_level_trusting_is_kept() {
+    use biscuit_auth::KeyPair;
+
+    let external = KeyPair::new().public();
+    let builder = AuthorizerBuilder::new()
+        .code("allow if true;")
+        .unwrap()
+        .scope(Scope::Authority)
+        .scope(Scope::PublicKey(external));
+    let snapshot =
+        AuthorizerBuilder::from_raw_snapshot(&builder.to_raw_snapshot().unwrap()).unwrap();
+    assert_eq!(
+        authorizer_scopes(&snapshot).unwrap(),
+        vec![Scope::Authority, Scope::PublicKey(external)]
+    );
+    assert_eq!(
+        authorizer_scopes(&AuthorizerBuilder::new()).unwrap(),
+        vec![]
+    );
 }
@@ -8,23 +8,34 @@ use biscuit_auth::{
     builder::{Fact, Rule},
     datalog::RunLimits,
     error::{FailedCheck, Logic, MatchedPolicy, RunLimit, Token},
-    Authorizer, UnverifiedBiscuit,
+    Authorizer, PublicKey, UnverifiedBiscuit,
 };
 use chrono::offset::Utc;
 use serde::Serialize;
 use serde_json::json;
-use std::path::PathBuf;
 use std::{fmt::Display, fs};
 
 use crate::cli::*;
 use crate::errors::CliError::*;
+use crate::graph::*;
 use crate::input::*;
+use crate::keys::*;
+use crate::output::*;
+use crate::scopes::*;
+use crate::size::*;
+use crate::third_party::*;
+
+mod report;
+
+pub use report::ReportFormat;
 
 #[derive(Serialize, Debug)]
 struct TokenBlock {
     version: u32,
     code: String,
     external_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    external_key_fingerprint: Option<String>,
     revocation_id: String,
 }
 
@@ -73,6 +84,11 @@ impl Display for TokenDescription {
             writeln!(f, "== Datalog {} ==", get_version_string(block.version))?;
             writeln!(f, "{}", block.code)?;
 
+            if let Some(fingerprint) = &block.external_key_fingerprint {
+                writeln!(f, "== External key fingerprint ==")?;
+                writeln!(f, "{}", fingerprint)?;
+            }
+
             writeln!(f, "== Revocation id ==")?;
             writeln!(f, "{}", block.revocation_id)?;
             writeln!(f, "\n==========\n")?;
@@ -196,10 +212,109 @@ impl Display for AuthResult {
     }
 }
 
+#[derive(Serialize, Debug)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+enum ExternalKeyMismatch {
+    /// The block is not signed with the expected key, or is not a third-party block
+    Unexpected {
+        block: usize,
+        key: Option<String>,
+        expected: String,
+    },
+    MissingBlock {
+        block: usize,
+        expected: String,
+    },
+    /// The block is signed with a key which is not in the trusted keys file
+    Untrusted {
+        block: usize,
+        key: String,
+    },
+}
+
+impl Display for ExternalKeyMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExternalKeyMismatch::Unexpected {
+                block,
+                key: Some(key),
+                expected,
+            } => write!(
+                f,
+                "Block n°{} is signed by {}, expected {}",
+                block, key, expected
+            ),
+            ExternalKeyMismatch::Unexpected {
+                block,
+                key: None,
+                expected,
+            } => write!(
+                f,
+                "Block n°{} is not a third-party block, expected a block signed by {}",
+                block, expected
+            ),
+            ExternalKeyMismatch::MissingBlock { block, expected } => write!(
+                f,
+                "Block n°{} does not exist, expected a block signed by {}",
+                block, expected
+            ),
+            ExternalKeyMismatch::Untrusted { block, key } => write!(
+                f,
+                "Block n°{} is signed by {}, which is not a trusted external key",
+                block, key
+            ),
+        }
+    }
+}
+
+/// Compares the keys third-party blocks are signed with to the expected ones
+fn check_external_keys(
+    external_keys: &[Option<PublicKey>],
+    expected: &[ExpectedExternalKey],
+    trusted: Option<&[KeyPattern]>,
+) -> Vec<ExternalKeyMismatch> {
+    let mut mismatches = vec![];
+    for e in expected {
+        match external_keys.get(e.block) {
+            None => mismatches.push(ExternalKeyMismatch::MissingBlock {
+                block: e.block,
+                expected: e.key.to_string(),
+            }),
+            Some(key) if !key.is_some_and(|k| e.key.matches(&k)) => {
+                mismatches.push(ExternalKeyMismatch::Unexpected {
+                    block: e.block,
+                    key: key.map(|k| k.to_string()),
+                    expected: e.key.to_string(),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    if let Some(trusted) = trusted {
+        for (block, key) in external_keys.iter().enumerate() {
+            if let Some(key) = key {
+                if !trusted.iter().any(|t| t.matches(key)) {
+                    mismatches.push(ExternalKeyMismatch::Untrusted {
+                        block,
+                        key: key.to_string(),
+                    });
+                }
+            }
+        }
+    }
+    mismatches
+}
+
 #[derive(Serialize, Debug)]
 pub struct InspectionResults {
     token: TokenDescription,
+    #[serde(skip)]
+    graph: Option<String>,
+    size: Option<TokenSize>,
+    trust_scopes: Option<TrustScopes>,
     signatures_check: Option<bool>,
+    /// Only present when external keys are expected
+    external_keys_check: Option<Vec<ExternalKeyMismatch>>,
     auth: Option<AuthResult>,
     query: Option<QueryResult>,
 }
@@ -208,12 +323,33 @@ impl Display for InspectionResults {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.token.fmt(f)?;
 
+        if let Some(size) = &self.size {
+            size.fmt(f)?;
+        }
+
+        if let Some(trust_scopes) = &self.trust_scopes {
+            trust_scopes.fmt(f)?;
+        }
+
         match self.signatures_check {
             None => writeln!(f, "🙈 Public key check skipped 🔑")?,
             Some(true) => writeln!(f, "✅ Public key check succeeded 🔑")?,
             Some(false) => writeln!(f, "❌ Public key check failed 🔑")?,
         }
 
+        match &self.external_keys_check {
+            None => {}
+            Some(mismatches) if mismatches.is_empty() => {
+                writeln!(f, "✅ External keys check succeeded 🔏")?
+            }
+            Some(mismatches) => {
+                writeln!(f, "❌ External keys check failed 🔏")?;
+                for mismatch in mismatches {
+                    writeln!(f, "  {}", mismatch)?;
+                }
+            }
+        }
+
         match &self.auth {
             None => writeln!(f, "🙈 Datalog check skipped 🛡️")?,
             Some(auth_result) => auth_result.fmt(f)?,
@@ -232,6 +368,10 @@ impl InspectionResults {
             Err(SignaturesCheckFailed)?;
         }
 
+        if matches!(&self.external_keys_check, Some(mismatches) if !mismatches.is_empty()) {
+            Err(ExternalKeysCheckFailed)?;
+        }
+
         if let Some(ref auth) = self.auth {
             if auth.result.clone().into_result().is_err() {
                 Err(AuthorizationFailed)?;
@@ -334,6 +474,115 @@ impl SnapshotEvaluationResults {
     }
 }
 
+#[derive(Serialize, Debug)]
+struct AttenuationCase {
+    authorizer: String,
+    original: AuthResult,
+    attenuated: AuthResult,
+}
+
+impl AttenuationCase {
+    fn is_counterexample(&self) -> bool {
+        self.original.result.is_err() && !self.attenuated.result.is_err()
+    }
+}
+
+impl Display for AttenuationCase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (
+            self.original.result.is_err(),
+            self.attenuated.result.is_err(),
+        ) {
+            (false, false) => writeln!(f, "✅ {}: authorized with both biscuits", self.authorizer),
+            (false, true) => writeln!(
+                f,
+                "✅ {}: only authorized with the original biscuit",
+                self.authorizer
+            ),
+            (true, true) => writeln!(f, "✅ {}: denied with both biscuits", self.authorizer),
+            (true, false) => {
+                writeln!(
+                    f,
+                    "❌ {}: only authorized with the attenuated biscuit",
+                    self.authorizer
+                )?;
+                writeln!(f, "Original biscuit:")?;
+                self.original.fmt(f)?;
+                writeln!(f, "Attenuated biscuit:")?;
+                self.attenuated.fmt(f)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct AttenuationCheckResults {
+    cases: Vec<AttenuationCase>,
+    counterexamples: usize,
+}
+
+impl Display for AttenuationCheckResults {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for case in &self.cases {
+            case.fmt(f)?;
+        }
+        writeln!(f)?;
+        if self.counterexamples == 0 {
+            writeln!(
+                f,
+                "✅ The attenuated biscuit only narrows rights ({} authorizers)",
+                self.cases.len()
+            )
+        } else {
+            writeln!(
+                f,
+                "❌ The attenuated biscuit widens rights ({} counterexamples out of {} authorizers)",
+                self.counterexamples,
+                self.cases.len()
+            )
+        }
+    }
+}
+
+impl AttenuationCheckResults {
+    pub fn ensure_success(&self) -> Result<()> {
+        if self.counterexamples > 0 {
+            Err(AttenuationWidensRights(self.counterexamples))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parameters referenced by the query, if any. They must all be provided
+fn query_param_names(query: &Option<Rule>, params: &[Param]) -> Result<ParamNames> {
+    let names = query
+        .as_ref()
+        .map(ParamNames::from_rule)
+        .unwrap_or_default();
+    names.ensure_provided(params)?;
+    Ok(names)
+}
+
+/// Runs the authorization, and keeps the policies around to describe the result
+fn authorize(authorizer: &mut Authorizer, limits: RunLimits) -> AuthResult {
+    let (_, _, _, policies) = authorizer.dump();
+    let authorizer_result = authorizer.authorize_with_limits(limits);
+
+    AuthResult {
+        policies: policies.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+        result: authorizer_result
+            .map(|i| {
+                (
+                    i,
+                    policies.get(i).expect("Incorrect policy index").to_string(),
+                )
+            })
+            .into(),
+        iterations: authorizer.iterations(),
+        elapsed_micros: authorizer.execution_time().map(|e| e.as_micros()),
+    }
+}
+
 fn handle_query(
     query: &Rule,
     query_all: bool,
@@ -375,7 +624,14 @@ fn handle_query(
 pub fn handle_inspect(inspect: &Inspect) -> Result<()> {
     match handle_inspect_inner(inspect) {
         Ok(res) => {
-            if inspect.json {
+            if let Some(path) = &inspect.report {
+                match inspect.report_format {
+                    ReportFormat::Html => fs::write(path, res.to_html())?,
+                }
+            }
+            if let Some(graph) = &res.graph {
+                print!("{}", graph);
+            } else if inspect.json {
                 println!("{}", serde_json::to_string(&res)?);
             } else {
                 println!("{}", &res);
@@ -392,20 +648,10 @@ pub fn handle_inspect(inspect: &Inspect) -> Result<()> {
 }
 
 pub fn handle_inspect_inner(inspect: &Inspect) -> Result<InspectionResults> {
-    let biscuit_format = if inspect.biscuit_input_args.raw_input {
-        BiscuitFormat::RawBiscuit
-    } else {
-        BiscuitFormat::Base64Biscuit
-    };
+    let biscuit_format = inspect.biscuit_input_args.biscuit_format();
 
-    let biscuit_from = if inspect.biscuit_input_args.biscuit_file == PathBuf::from("-") {
-        BiscuitBytes::FromStdin(biscuit_format)
-    } else {
-        BiscuitBytes::FromFile(
-            biscuit_format,
-            inspect.biscuit_input_args.biscuit_file.clone(),
-        )
-    };
+    let biscuit_from =
+        biscuit_bytes_from_path(&inspect.biscuit_input_args.biscuit_file, biscuit_format);
 
     let public_key_from = match (
         &inspect.public_key,
@@ -417,12 +663,15 @@ pub fn handle_inspect_inner(inspect: &Inspect) -> Result<InspectionResults> {
         }
         (Some(str), None, KeyFormat::Hex) => Some(KeyBytes::HexString(str.to_string())),
         (Some(str), None, KeyFormat::Pem) => Some(KeyBytes::PemString(str.to_string())),
-        (None, Some(path), f) => Some(KeyBytes::FromFile(*f, path.to_path_buf())),
+        (None, Some(path), f) => Some(key_bytes_from_path(path, *f)),
         (None, None, _) => None,
         // the other combinations are prevented by clap
         _ => unreachable!(),
     };
 
+    let params = inspect.param_arg.params()?;
+    let mut referenced = query_param_names(&inspect.query_args.query, &params)?;
+
     let authorizer_from = match (
         &inspect.authorization_args.authorize_interactive,
         &inspect.authorization_args.authorize_with,
@@ -433,41 +682,52 @@ pub fn handle_inspect_inner(inspect: &Inspect) -> Result<InspectionResults> {
         (false, None, None, None, None) => None,
         (true, None, None, None, None) => Some(AuthorizerInput::FromDatalog(
             DatalogInput::FromEditor,
-            inspect.param_arg.param.clone(),
+            params.clone(),
         )),
         (false, Some(str), None, None, None) => Some(AuthorizerInput::FromDatalog(
             DatalogInput::DatalogString(str.to_owned()),
-            inspect.param_arg.param.clone(),
+            params.clone(),
         )),
         (false, None, Some(path), None, None) => Some(AuthorizerInput::FromDatalog(
             DatalogInput::FromFile(path.to_path_buf()),
-            inspect.param_arg.param.clone(),
+            params.clone(),
         )),
         (false, None, None, Some(str), None) => Some(AuthorizerInput::FromSnapshot(
-            SnapshotInput::FromString(str.to_owned()),
+            BiscuitBytes::Base64String(str.to_owned()),
         )),
         (false, None, None, None, Some(path)) => {
-            Some(AuthorizerInput::FromSnapshot(SnapshotInput::FromFile(
-                path.to_path_buf(),
-                if inspect.authorization_args.authorize_with_raw_snapshot_file {
-                    BiscuitFormat::RawBiscuit
-                } else {
-                    BiscuitFormat::Base64Biscuit
-                },
+            Some(AuthorizerInput::FromSnapshot(biscuit_bytes_from_path(
+                path,
+                BiscuitFormat::from_flags(
+                    inspect.authorization_args.authorize_with_raw_snapshot_file,
+                    inspect
+                        .authorization_args
+                        .authorize_with_base64_snapshot_file,
+                ),
             )))
         }
         // the other combinations are prevented by clap
         _ => unreachable!(),
     };
 
-    if let Some(AuthorizerInput::FromDatalog(dlf, _)) = &authorizer_from {
-        ensure_no_input_conflict(dlf, &biscuit_from)?;
+    match &authorizer_from {
+        Some(AuthorizerInput::FromDatalog(dlf, _)) => ensure_no_input_conflict(dlf, &biscuit_from)?,
+        Some(AuthorizerInput::FromSnapshot(snapshot_from)) => {
+            ensure_no_input_conflict_third_party(snapshot_from, &biscuit_from)?
+        }
+        None => {}
     }
 
     if inspect.query_args.query.is_some() && public_key_from.is_none() {
         Err(MissingPublicKeyForQuerying)?;
     }
 
+    let checks_external_keys =
+        inspect.trusted_external_keys.is_some() || !inspect.expect_external_key.is_empty();
+    if checks_external_keys && public_key_from.is_none() {
+        Err(MissingPublicKeyForExternalKeys)?;
+    }
+
     let biscuit = read_biscuit_from(&biscuit_from)?;
     let is_sealed = is_sealed(&biscuit)?;
 
@@ -476,13 +736,11 @@ pub fn handle_inspect_inner(inspect: &Inspect) -> Result<InspectionResults> {
     let revocation_ids = biscuit.revocation_identifiers();
     let external_keys = biscuit.external_public_keys();
     for i in 0..biscuit.block_count() {
-        let external_key = external_keys
-            .get(i)
-            .expect("Incorrect block index")
-            .map(|pk| pk.to_string());
+        let external_key = external_keys.get(i).expect("Incorrect block index");
         blocks.push(TokenBlock {
             code: biscuit.print_block_source(i)?,
-            external_key,
+            external_key: external_key.map(|pk| pk.to_string()),
+            external_key_fingerprint: external_key.as_ref().map(fingerprint),
             revocation_id: revocation_ids
                 .get(i)
                 .map(hex::encode)
@@ -497,9 +755,22 @@ pub fn handle_inspect_inner(inspect: &Inspect) -> Result<InspectionResults> {
         blocks,
     };
 
+    let size = if inspect.size {
+        Some(token_size(&biscuit.to_vec()?)?)
+    } else {
+        None
+    };
+
+    let block_codes = if inspect.graph.is_some() || inspect.trust_scopes {
+        Some(token_blocks(&biscuit)?)
+    } else {
+        None
+    };
+
     let signatures_check;
     let auth_result;
     let query_result;
+    let mut authorizer_code = None;
 
     if let Some(key_from) = public_key_from {
         let key = read_public_key_from(&key_from, &inspect.public_key_algorithm)?;
@@ -509,82 +780,70 @@ pub fn handle_inspect_inner(inspect: &Inspect) -> Result<InspectionResults> {
         if let Ok(biscuit) = sig_result {
             let mut authorizer_builder;
             if let Some(auth_from) = authorizer_from {
-                authorizer_builder = read_authorizer_from(&auth_from)?;
+                authorizer_builder = read_authorizer_from(&auth_from, &mut referenced)?;
+                ensure_params_used(&params, &referenced, inspect.param_arg.strict_params)?;
                 if inspect.authorization_args.include_time {
                     let now = Utc::now().to_rfc3339();
                     let time_fact = format!("time({})", now);
                     authorizer_builder = authorizer_builder.fact(time_fact.as_ref())?;
                 }
                 if let Some(policies_snapshot_file) = &inspect.dump_policies_snapshot_to {
-                    if inspect.dump_raw_policies_snapshot {
-                        let bytes = authorizer_builder.to_raw_snapshot()?;
-                        fs::write(policies_snapshot_file, bytes)?;
-                    } else {
-                        let str = authorizer_builder.to_base64_snapshot()?;
-                        fs::write(policies_snapshot_file, str)?;
-                    }
+                    let encoded = encode_output(
+                        authorizer_builder.to_raw_snapshot()?,
+                        &OutputFormat::from_flags(
+                            inspect.dump_raw_policies_snapshot,
+                            inspect.dump_armored_policies_snapshot,
+                        ),
+                        POLICIES_SNAPSHOT_LABEL,
+                        &[],
+                    );
+                    fs::write(policies_snapshot_file, encoded)?;
                 }
 
+                if inspect.graph.is_some() || inspect.trust_scopes {
+                    authorizer_code = Some((
+                        authorizer_builder.dump_code(),
+                        authorizer_scopes(&authorizer_builder)?,
+                    ));
+                }
                 let mut authorizer = authorizer_builder.build(&biscuit)?;
-                let (_, _, _, policies) = authorizer.dump();
-
-                let authorizer_result = authorizer.authorize_with_limits(RunLimits {
-                    max_facts: inspect
-                        .run_limits_args
-                        .max_facts
-                        .unwrap_or_else(|| RunLimits::default().max_facts),
-                    max_iterations: inspect
-                        .run_limits_args
-                        .max_iterations
-                        .unwrap_or_else(|| RunLimits::default().max_iterations),
-                    max_time: inspect
-                        .run_limits_args
-                        .max_time
-                        .map_or_else(|| RunLimits::default().max_time, |d| d.to_std().unwrap()),
-                });
-
-                auth_result = Some(AuthResult {
-                    policies: policies.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
-                    result: authorizer_result
-                        .map(|i| {
-                            (
-                                i,
-                                policies.get(i).expect("Incorrect policy index").to_string(),
-                            )
-                        })
-                        .into(),
-                    iterations: authorizer.iterations(),
-                    elapsed_micros: authorizer.execution_time().map(|e| e.as_micros()),
-                });
+                auth_result = Some(authorize(
+                    &mut authorizer,
+                    inspect.run_limits_args.run_limits(),
+                ));
 
                 if let Some(snapshot_file) = &inspect.dump_snapshot_to {
-                    if inspect.dump_raw_snapshot {
-                        let bytes = authorizer.to_raw_snapshot()?;
-                        fs::write(snapshot_file, bytes)?;
-                    } else {
-                        let str = authorizer.to_base64_snapshot()?;
-                        fs::write(snapshot_file, str)?;
-                    }
+                    let encoded = encode_output(
+                        authorizer.to_raw_snapshot()?,
+                        &OutputFormat::from_flags(
+                            inspect.dump_raw_snapshot,
+                            inspect.dump_armored_snapshot,
+                        ),
+                        SNAPSHOT_LABEL,
+                        &[],
+                    );
+                    fs::write(snapshot_file, encoded)?;
                 }
 
                 if let Some(query) = &inspect.query_args.query {
                     query_result = Some(handle_query(
                         query,
                         inspect.query_args.query_all,
-                        &inspect.param_arg.param,
+                        &params,
                         &mut authorizer,
                     )?);
                 } else {
                     query_result = None;
                 }
             } else {
+                ensure_params_used(&params, &referenced, inspect.param_arg.strict_params)?;
                 auth_result = None;
                 let mut authorizer = biscuit.authorizer()?;
                 if let Some(query) = &inspect.query_args.query {
                     query_result = Some(handle_query(
                         query,
                         inspect.query_args.query_all,
-                        &inspect.param_arg.param,
+                        &params,
                         &mut authorizer,
                     )?);
                 } else {
@@ -605,14 +864,518 @@ pub fn handle_inspect_inner(inspect: &Inspect) -> Result<InspectionResults> {
         }
     }
 
+    // external keys can be forged in a token which was not signed with the root key
+    let external_keys_check = match &inspect.trusted_external_keys {
+        _ if signatures_check != Some(true) => None,
+        Some(path) => Some(check_external_keys(
+            &external_keys,
+            &inspect.expect_external_key,
+            Some(&read_keyring(path)?),
+        )),
+        None if checks_external_keys => Some(check_external_keys(
+            &external_keys,
+            &inspect.expect_external_key,
+            None,
+        )),
+        None => None,
+    };
+
+    let authorizer = authorizer_code
+        .map(|(code, scopes)| {
+            let mut authorizer = BlockCode::parse_authorizer(&code)?;
+            authorizer.scopes = scopes;
+            Ok::<_, anyhow::Error>(authorizer)
+        })
+        .transpose()?;
+    let external_keys = token_description
+        .blocks
+        .iter()
+        .map(|b| b.external_key.clone())
+        .collect::<Vec<_>>();
+
+    let trust_scopes = match (inspect.trust_scopes, &block_codes) {
+        (true, Some(blocks)) => Some(trust_scopes(blocks, &external_keys, authorizer.as_ref())),
+        _ => None,
+    };
+
+    let graph = match (inspect.graph, block_codes) {
+        (Some(format), Some(blocks)) => {
+            let blocks = blocks
+                .into_iter()
+                .zip(external_keys)
+                .map(|(code, external_key)| GraphBlock { code, external_key })
+                .collect::<Vec<_>>();
+            Some(render_graph(&blocks, authorizer.as_ref(), format))
+        }
+        _ => None,
+    };
+
     Ok(InspectionResults {
         token: token_description,
+        graph,
+        size,
+        trust_scopes,
         signatures_check,
+        external_keys_check,
         auth: auth_result,
         query: query_result,
     })
 }
 
+pub fn handle_check_attenuation(check: &CheckAttenuation) -> Result<()> {
+    match handle_check_attenuation_inner(check) {
+        Ok(res) => {
+            if check.json {
+                println!("{}", serde_json::to_string(&res)?);
+            } else {
+                println!("{}", &res);
+            }
+            res.ensure_success()
+        }
+        Err(e) => {
+            if check.json {
+                println!("{}", json!({ "error": e.to_string() }))
+            }
+            Err(e)
+        }
+    }
+}
+
+pub fn handle_check_attenuation_inner(check: &CheckAttenuation) -> Result<AttenuationCheckResults> {
+    let biscuit_format = || BiscuitFormat::from_flags(check.raw_input, check.base64_input);
+    let original_from = biscuit_bytes_from_path(&check.original_file, biscuit_format());
+    let attenuated_from = biscuit_bytes_from_path(&check.attenuated_file, biscuit_format());
+    ensure_no_input_conflict_third_party(&original_from, &attenuated_from)?;
+
+    let public_key_from = match (
+        &check.public_key,
+        &check.public_key_file,
+        &check.public_key_format,
+    ) {
+        (Some(_), _, KeyFormat::Raw) => {
+            bail!("raw key input is only allowed from a file or stdin")
+        }
+        (Some(str), None, KeyFormat::Hex) => KeyBytes::HexString(str.to_string()),
+        (Some(str), None, KeyFormat::Pem) => KeyBytes::PemString(str.to_string()),
+        (None, Some(path), f) => key_bytes_from_path(path, *f),
+        // the other combinations are prevented by clap
+        _ => unreachable!(),
+    };
+    let key = read_public_key_from(&public_key_from, &check.public_key_algorithm)?;
+
+    let original = read_biscuit_from(&original_from)?;
+    let attenuated = read_biscuit_from(&attenuated_from)?;
+    if !attenuated
+        .revocation_identifiers()
+        .starts_with(&original.revocation_identifiers())
+    {
+        Err(NotAnAttenuation)?;
+    }
+    let original = original.verify(key).map_err(|_| SignaturesCheckFailed)?;
+    let attenuated = attenuated.verify(key).map_err(|_| SignaturesCheckFailed)?;
+
+    let params = check.param_arg.params()?;
+    let mut referenced = ParamNames::default();
+    let now = Utc::now().to_rfc3339();
+    let mut cases = vec![];
+
+    for path in read_corpus_paths(&check.corpus)? {
+        let mut authorizer_builder = read_authorizer_from_datalog(
+            &DatalogInput::FromFile(path.clone()),
+            &params,
+            &mut referenced,
+        )?;
+        if check.include_time {
+            let time_fact = format!("time({})", now);
+            authorizer_builder = authorizer_builder.fact(time_fact.as_ref())?;
+        }
+
+        let mut authorizer = authorizer_builder.clone().build(&original)?;
+        let original_result = authorize(&mut authorizer, check.run_limits_args.run_limits());
+        let mut authorizer = authorizer_builder.build(&attenuated)?;
+        let attenuated_result = authorize(&mut authorizer, check.run_limits_args.run_limits());
+
+        cases.push(AttenuationCase {
+            authorizer: path.display().to_string(),
+            original: original_result,
+            attenuated: attenuated_result,
+        });
+    }
+    ensure_params_used(&params, &referenced, check.param_arg.strict_params)?;
+
+    Ok(AttenuationCheckResults {
+        counterexamples: cases.iter().filter(|c| c.is_counterexample()).count(),
+        cases,
+    })
+}
+
+#[derive(Serialize, Debug)]
+struct RequestTokenMatch {
+    /// Index of the block whose signature was used to generate the request
+    block: Option<usize>,
+    block_count: usize,
+    sealed: bool,
+}
+
+impl RequestTokenMatch {
+    fn is_match(&self) -> bool {
+        !self.sealed && self.block.is_some() && self.block == self.block_count.checked_sub(1)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RequestInspectionResults {
+    previous_signature: String,
+    previous_key: Option<String>,
+    /// v1 requests do not carry the previous block public key, it is then read from
+    /// the matching block of the biscuit
+    previous_key_from: Option<SignatureTarget>,
+    token: Option<RequestTokenMatch>,
+}
+
+impl Display for RequestInspectionResults {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Third-party block request")?;
+        writeln!(
+            f,
+            "== Previous block signature (revocation id) ==\n{}",
+            self.previous_signature
+        )?;
+        match (&self.previous_key, &self.previous_key_from) {
+            (Some(key), Some(SignatureTarget::Biscuit)) => writeln!(
+                f,
+                "== Previous block public key ==\n{} (from the biscuit, the request does not carry it)",
+                key
+            )?,
+            (Some(key), _) => writeln!(f, "== Previous block public key ==\n{}", key)?,
+            (None, _) => writeln!(
+                f,
+                "== Previous block public key ==\nNot carried by the request, use --biscuit-file to read it from the biscuit"
+            )?,
+        }
+        writeln!(f, "\n==========\n")?;
+
+        match &self.token {
+            None => writeln!(f, "🙈 Biscuit check skipped"),
+            Some(token) if token.sealed => {
+                writeln!(f, "❌ The biscuit is sealed, no block can be appended to it")
+            }
+            Some(token) if token.is_match() => writeln!(
+                f,
+                "✅ The request was generated from the last block of the biscuit (block n°{})",
+                token.block_count - 1
+            ),
+            Some(RequestTokenMatch {
+                block: Some(block),
+                block_count,
+                ..
+            }) => writeln!(
+                f,
+                "❌ The request was generated from block n°{}, but the biscuit has {} blocks: it has been attenuated since",
+                block, block_count
+            ),
+            Some(_) => writeln!(
+                f,
+                "❌ The request was not generated from this biscuit"
+            ),
+        }
+    }
+}
+
+impl RequestInspectionResults {
+    pub fn ensure_success(&self) -> Result<()> {
+        match &self.token {
+            Some(token) if !token.is_match() => Err(RequestMismatch)?,
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn handle_inspect_request(inspect_request: &InspectRequest) -> Result<()> {
+    match handle_inspect_request_inner(inspect_request) {
+        Ok(res) => {
+            if inspect_request.json {
+                println!("{}", serde_json::to_string(&res)?);
+            } else {
+                println!("{}", &res);
+            }
+            res.ensure_success()
+        }
+        Err(e) => {
+            if inspect_request.json {
+                println!("{}", json!({ "error": e.to_string() }))
+            }
+            Err(e)
+        }
+    }
+}
+
+pub fn handle_inspect_request_inner(
+    inspect_request: &InspectRequest,
+) -> Result<RequestInspectionResults> {
+    let request_from = biscuit_bytes_from_path(
+        &inspect_request.request_file,
+        BiscuitFormat::from_flags(inspect_request.raw_input, inspect_request.base64_input),
+    );
+    let biscuit_from = inspect_request.biscuit_file.as_ref().map(|path| {
+        biscuit_bytes_from_path(
+            path,
+            BiscuitFormat::from_flags(inspect_request.raw_biscuit, inspect_request.base64_biscuit),
+        )
+    });
+    if let Some(biscuit_from) = &biscuit_from {
+        ensure_no_input_conflict_third_party(&request_from, biscuit_from)?;
+    }
+
+    let request = read_request_from(&request_from)?;
+    let previous_signature = request_previous_signature(&request)?;
+    let mut previous_key =
+        request_previous_key(&request)?.map(|key| (key.to_string(), SignatureTarget::Request));
+
+    let token = match biscuit_from {
+        Some(biscuit_from) => {
+            let biscuit = read_biscuit_from(&biscuit_from)?;
+            let block = biscuit
+                .revocation_identifiers()
+                .iter()
+                .position(|id| *id == previous_signature);
+            if let (None, Some(block)) = (&previous_key, block) {
+                previous_key = Some((
+                    block_next_keys(&biscuit)?[block].to_string(),
+                    SignatureTarget::Biscuit,
+                ));
+            }
+            Some(RequestTokenMatch {
+                block,
+                block_count: biscuit.block_count(),
+                sealed: is_sealed(&biscuit)?,
+            })
+        }
+        None => None,
+    };
+
+    let (previous_key, previous_key_from) = previous_key.unzip();
+    Ok(RequestInspectionResults {
+        previous_signature: hex::encode(previous_signature),
+        previous_key,
+        previous_key_from,
+        token,
+    })
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum SignatureTarget {
+    Request,
+    Biscuit,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ThirdPartyBlockInspectionResults {
+    external_key: String,
+    external_key_fingerprint: String,
+    version: u32,
+    context: Option<String>,
+    code: String,
+    /// What the external signature was checked against, if anything
+    checked_against: Option<SignatureTarget>,
+    signature_check: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature_error: Option<String>,
+    sealed: Option<bool>,
+}
+
+impl Display for ThirdPartyBlockInspectionResults {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Third-party block, signed by {}", self.external_key)?;
+        writeln!(
+            f,
+            "External key fingerprint: {}",
+            self.external_key_fingerprint
+        )?;
+        writeln!(
+            f,
+            "== Datalog {} ==\n{}",
+            get_version_string(self.version),
+            self.code
+        )?;
+        if let Some(context) = &self.context {
+            writeln!(f, "== Context ==\n{}", context)?;
+        }
+        writeln!(f, "\n==========\n")?;
+
+        match (&self.checked_against, self.signature_check) {
+            (Some(SignatureTarget::Request), Some(true)) => {
+                writeln!(f, "✅ The block was signed for the request")?
+            }
+            (Some(SignatureTarget::Request), Some(false)) => writeln!(
+                f,
+                "❌ The block was not signed for the request: {}",
+                self.signature_error.as_deref().unwrap_or_default()
+            )?,
+            (Some(SignatureTarget::Biscuit), Some(true)) => writeln!(
+                f,
+                "✅ The block was signed for the last block of the biscuit"
+            )?,
+            (Some(SignatureTarget::Biscuit), Some(false)) => writeln!(
+                f,
+                "❌ The block can't be appended to the biscuit: {}",
+                self.signature_error.as_deref().unwrap_or_default()
+            )?,
+            (Some(SignatureTarget::Biscuit), None) if self.sealed != Some(true) => writeln!(
+                f,
+                "🙈 Signature check skipped: the root public key is required to check the block against the biscuit"
+            )?,
+            _ => writeln!(f, "🙈 Signature check skipped")?,
+        }
+        if self.sealed == Some(true) {
+            writeln!(
+                f,
+                "❌ The biscuit is sealed, no block can be appended to it"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl ThirdPartyBlockInspectionResults {
+    fn new(
+        block: &ThirdPartyBlockContents,
+        checked_against: Option<SignatureTarget>,
+        signature_check: Option<std::result::Result<(), String>>,
+        sealed: Option<bool>,
+    ) -> Self {
+        ThirdPartyBlockInspectionResults {
+            external_key: block.external_key.to_string(),
+            external_key_fingerprint: fingerprint(&block.external_key),
+            version: block.version,
+            context: block.context.clone(),
+            code: block.code.to_string(),
+            checked_against,
+            signature_check: signature_check.as_ref().map(|r| r.is_ok()),
+            signature_error: signature_check.and_then(|r| r.err()),
+            sealed,
+        }
+    }
+
+    /// Checks the block against the biscuit it is meant to be appended to. The external
+    /// signature is only verified by biscuit-auth along with the whole token, so this
+    /// requires the root public key
+    pub fn for_biscuit(
+        block: &ThirdPartyBlockContents,
+        biscuit: &UnverifiedBiscuit,
+        root_key: Option<PublicKey>,
+    ) -> Result<Self> {
+        let sealed = is_sealed(biscuit)?;
+        let signature_check = root_key.filter(|_| !sealed).map(|key| {
+            biscuit
+                .append_third_party(&block.bytes)
+                .map_err(|e| e.to_string())
+                .and_then(|b| b.verify(key).map_err(|e| e.to_string()))
+                .map(|_| ())
+        });
+        Ok(Self::new(
+            block,
+            Some(SignatureTarget::Biscuit),
+            signature_check,
+            Some(sealed),
+        ))
+    }
+
+    pub fn ensure_success(&self) -> Result<()> {
+        if let Some(error) = &self.signature_error {
+            Err(ThirdPartyBlockMismatch(error.clone()))?
+        }
+        if self.sealed == Some(true) {
+            Err(SealedBiscuit)?
+        }
+        Ok(())
+    }
+}
+
+pub fn handle_inspect_third_party_block(inspect_block: &InspectThirdPartyBlock) -> Result<()> {
+    match handle_inspect_third_party_block_inner(inspect_block) {
+        Ok(res) => {
+            if inspect_block.json {
+                println!("{}", serde_json::to_string(&res)?);
+            } else {
+                println!("{}", &res);
+            }
+            res.ensure_success()
+        }
+        Err(e) => {
+            if inspect_block.json {
+                println!("{}", json!({ "error": e.to_string() }))
+            }
+            Err(e)
+        }
+    }
+}
+
+pub fn handle_inspect_third_party_block_inner(
+    inspect_block: &InspectThirdPartyBlock,
+) -> Result<ThirdPartyBlockInspectionResults> {
+    let block_from = biscuit_bytes_from_path(
+        &inspect_block.block_file,
+        BiscuitFormat::from_flags(inspect_block.raw_input, inspect_block.base64_input),
+    );
+    let request_from = inspect_block.request_file.as_ref().map(|path| {
+        biscuit_bytes_from_path(
+            path,
+            BiscuitFormat::from_flags(inspect_block.raw_request, inspect_block.base64_request),
+        )
+    });
+    let biscuit_from = inspect_block.biscuit_file.as_ref().map(|path| {
+        biscuit_bytes_from_path(
+            path,
+            BiscuitFormat::from_flags(inspect_block.raw_biscuit, inspect_block.base64_biscuit),
+        )
+    });
+    if let Some(other) = request_from.as_ref().or(biscuit_from.as_ref()) {
+        ensure_no_input_conflict_third_party(&block_from, other)?;
+    }
+
+    let block = read_third_party_block_from(&block_from)?;
+
+    match (request_from, biscuit_from) {
+        (Some(request_from), _) => {
+            let request = read_request_from(&request_from)?;
+            let signature_check = block.check_request(&request)?;
+            Ok(ThirdPartyBlockInspectionResults::new(
+                &block,
+                Some(SignatureTarget::Request),
+                Some(signature_check),
+                None,
+            ))
+        }
+        (None, Some(biscuit_from)) => {
+            let biscuit = read_biscuit_from(&biscuit_from)?;
+            let key_from = match (
+                &inspect_block.public_key,
+                &inspect_block.public_key_file,
+                &inspect_block.public_key_format,
+            ) {
+                (Some(_), _, KeyFormat::Raw) => {
+                    bail!("raw key input is only allowed from a file or stdin")
+                }
+                (Some(str), None, KeyFormat::Hex) => Some(KeyBytes::HexString(str.to_string())),
+                (Some(str), None, KeyFormat::Pem) => Some(KeyBytes::PemString(str.to_string())),
+                (None, Some(path), f) => Some(key_bytes_from_path(path, *f)),
+                (None, None, _) => None,
+                // the other combinations are prevented by clap
+                _ => unreachable!(),
+            };
+            let root_key = key_from
+                .map(|from| read_public_key_from(&from, &inspect_block.public_key_algorithm))
+                .transpose()?;
+            ThirdPartyBlockInspectionResults::for_biscuit(&block, &biscuit, root_key)
+        }
+        (None, None) => Ok(ThirdPartyBlockInspectionResults::new(
+            &block, None, None, None,
+        )),
+    }
+}
+
 pub fn handle_inspect_snapshot(inspect_snapshot: &InspectSnapshot) -> Result<()> {
     match handle_inspect_snapshot_inner(inspect_snapshot) {
         Ok(res) => {
@@ -635,17 +1398,10 @@ pub fn handle_inspect_snapshot(inspect_snapshot: &InspectSnapshot) -> Result<()>
 pub fn handle_inspect_snapshot_inner(
     inspect_snapshot: &InspectSnapshot,
 ) -> Result<SnapshotInspectionResults> {
-    let snapshot_format = if inspect_snapshot.raw_input {
-        BiscuitFormat::RawBiscuit
-    } else {
-        BiscuitFormat::Base64Biscuit
-    };
+    let snapshot_format =
+        BiscuitFormat::from_flags(inspect_snapshot.raw_input, inspect_snapshot.base64_input);
 
-    let snapshot_from = if inspect_snapshot.snapshot_file == PathBuf::from("-") {
-        BiscuitBytes::FromStdin(snapshot_format)
-    } else {
-        BiscuitBytes::FromFile(snapshot_format, inspect_snapshot.snapshot_file.clone())
-    };
+    let snapshot_from = biscuit_bytes_from_path(&inspect_snapshot.snapshot_file, snapshot_format);
 
     let mut authorizer = read_snapshot_from(&snapshot_from)?;
 
@@ -656,7 +1412,14 @@ pub fn handle_inspect_snapshot_inner(
         already_evaluated: authorizer.execution_time().is_some(),
     };
 
-    let evaluation = handle_snapshot_evaluation(inspect_snapshot, &mut authorizer);
+    let params = inspect_snapshot.param_arg.params()?;
+    let referenced = query_param_names(&inspect_snapshot.query_args.query, &params)?;
+    ensure_params_used(
+        &params,
+        &referenced,
+        inspect_snapshot.param_arg.strict_params,
+    )?;
+    let evaluation = handle_snapshot_evaluation(inspect_snapshot, &params, &mut authorizer);
 
     Ok(SnapshotInspectionResults {
         contents,
@@ -666,6 +1429,7 @@ pub fn handle_inspect_snapshot_inner(
 
 fn handle_snapshot_evaluation(
     inspect_snapshot: &InspectSnapshot,
+    params: &[Param],
     authorizer: &mut Authorizer,
 ) -> std::result::Result<SnapshotEvaluationResults, Token> {
     let _ = authorizer.run()?;
@@ -673,20 +1437,8 @@ fn handle_snapshot_evaluation(
     let (_, _, _, policies) = authorizer.dump();
     let policies: Vec<String> = policies.into_iter().map(|p| p.to_string()).collect();
 
-    let authorizer_result = authorizer.authorize_with_limits(RunLimits {
-        max_facts: inspect_snapshot
-            .run_limits_args
-            .max_facts
-            .unwrap_or_else(|| RunLimits::default().max_facts),
-        max_iterations: inspect_snapshot
-            .run_limits_args
-            .max_iterations
-            .unwrap_or_else(|| RunLimits::default().max_iterations),
-        max_time: inspect_snapshot
-            .run_limits_args
-            .max_time
-            .map_or_else(|| RunLimits::default().max_time, |d| d.to_std().unwrap()),
-    });
+    let authorizer_result =
+        authorizer.authorize_with_limits(inspect_snapshot.run_limits_args.run_limits());
 
     let result = match authorizer_result {
         Ok(policy_id) => RResult::Ok((
@@ -714,7 +1466,7 @@ fn handle_snapshot_evaluation(
             handle_query(
                 query,
                 inspect_snapshot.query_args.query_all,
-                &inspect_snapshot.param_arg.param,
+                params,
                 authorizer,
             )
         })
@@ -812,3 +1564,285 @@ fn is_sealed(b: &UnverifiedBiscuit) -> Result<bool> {
         Err(e) => Err(e.into()),
     }
 }
+
+#[test]
+fn third_party_block_against_biscuit() {
+    use biscuit_auth::{Biscuit, BlockBuilder, KeyPair};
+
+    let root = KeyPair::new();
+    let external = KeyPair::new();
+    let biscuit = |fact: &str| {
+        let token = Biscuit::builder().fact(fact).unwrap().build(&root).unwrap();
+        UnverifiedBiscuit::from(token.to_vec().unwrap()).unwrap()
+    };
+    let (biscuit, other) = (biscuit("user(\"1234\")"), biscuit("user(\"5678\")"));
+    let block = biscuit
+        .third_party_request()
+        .unwrap()
+        .create_block(
+            &external.private(),
+            BlockBuilder::new().fact("group(\"admin\")").unwrap(),
+        )
+        .unwrap();
+    let block = ThirdPartyBlockContents::decode(&block.serialize().unwrap()).unwrap();
+
+    let results =
+        ThirdPartyBlockInspectionResults::for_biscuit(&block, &biscuit, Some(root.public()))
+            .unwrap();
+    assert_eq!(results.signature_check, Some(true));
+    assert!(results.ensure_success().is_ok());
+
+    let results =
+        ThirdPartyBlockInspectionResults::for_biscuit(&block, &other, Some(root.public())).unwrap();
+    assert_eq!(results.signature_check, Some(false));
+    assert!(results.ensure_success().is_err());
+
+    let results = ThirdPartyBlockInspectionResults::for_biscuit(&block, &other, None).unwrap();
+    assert_eq!(results.signature_check, None);
+}
+
+#[test]
+fn check_attenuation_counterexamples() {
+    use biscuit_auth::{Biscuit, BlockBuilder, KeyPair};
+    use clap::Parser;
+
+    let root = KeyPair::new();
+    let external = KeyPair::new();
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, contents: Vec<u8>| {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        path.display().to_string()
+    };
+    // the original token only allows reading
+    fs::create_dir(dir.path().join("corpus")).unwrap();
+    for op in ["read", "write"] {
+        let authorizer = format!(
+            "operation(\"{}\"); allow if right($op), operation($op);",
+            op
+        );
+        write(&format!("corpus/{}.datalog", op), authorizer.into_bytes());
+    }
+    let corpus = dir.path().join("corpus").display().to_string();
+    let check = |original: &str, attenuated: &str| {
+        let root_key = root.public().to_string();
+        // the default time limit is easily reached by debug builds running tests in parallel
+        handle_check_attenuation_inner(&CheckAttenuation::parse_from([
+            "check-attenuation",
+            "--public-key",
+            &root_key,
+            "--max-time",
+            "1s",
+            "--corpus",
+            &corpus,
+            original,
+            attenuated,
+        ]))
+    };
+
+    let original = Biscuit::builder()
+        .code(format!(
+            "right(\"read\"); right(\"write\"); check if operation(\"read\") or group(\"admin\") trusting {};",
+            external.public()
+        ))
+        .unwrap()
+        .build(&root)
+        .unwrap();
+    let original_file = write("original", original.to_vec().unwrap());
+
+    let narrowed = original
+        .append(
+            BlockBuilder::new()
+                .code("check if operation(\"read\");")
+                .unwrap(),
+        )
+        .unwrap();
+    let narrowed_file = write("narrowed", narrowed.to_vec().unwrap());
+    assert_eq!(
+        check(&original_file, &narrowed_file)
+            .unwrap()
+            .counterexamples,
+        0
+    );
+
+    let block = original
+        .third_party_request()
+        .unwrap()
+        .create_block(
+            &external.private(),
+            BlockBuilder::new().code("group(\"admin\");").unwrap(),
+        )
+        .unwrap();
+    let widened = original
+        .append_third_party(external.public(), block)
+        .unwrap();
+    let widened_file = write("widened", widened.to_vec().unwrap());
+    assert_eq!(
+        check(&original_file, &widened_file)
+            .unwrap()
+            .counterexamples,
+        1
+    );
+
+    let unrelated = Biscuit::builder()
+        .code("right(\"read\");")
+        .unwrap()
+        .build(&root)
+        .unwrap();
+    let unrelated_file = write("unrelated", unrelated.to_vec().unwrap());
+    let error = check(&original_file, &unrelated_file).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(crate::errors::CliError::NotAnAttenuation)
+    ));
+}
+
+#[test]
+fn inspect_request_against_biscuits() {
+    use biscuit_auth::{Biscuit, KeyPair};
+    use clap::Parser;
+
+    let root = KeyPair::new();
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, contents: Vec<u8>| {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        path.display().to_string()
+    };
+    let biscuit = |fact: &str| Biscuit::builder().fact(fact).unwrap().build(&root).unwrap();
+    let token = biscuit("user(\"1234\")");
+    let request = write(
+        "request",
+        token.third_party_request().unwrap().serialize().unwrap(),
+    );
+    let inspect = |biscuit: &Biscuit| {
+        let biscuit_file = write("biscuit", biscuit.to_vec().unwrap());
+        handle_inspect_request_inner(&InspectRequest::parse_from([
+            "inspect-request",
+            "--biscuit-file",
+            &biscuit_file,
+            &request,
+        ]))
+        .unwrap()
+    };
+
+    let results = inspect(&token);
+    assert!(results.ensure_success().is_ok());
+    let token_match = results.token.as_ref().unwrap();
+    assert_eq!((token_match.block, token_match.sealed), (Some(0), false));
+    let next_key = block_next_keys(&UnverifiedBiscuit::from(token.to_vec().unwrap()).unwrap())
+        .unwrap()[0]
+        .to_string();
+    assert_eq!(results.previous_key, Some(next_key));
+
+    let results = inspect(&biscuit("user(\"5678\")"));
+    assert!(results.ensure_success().is_err());
+    assert_eq!(results.token.as_ref().unwrap().block, None);
+    assert_eq!(results.previous_key, None);
+
+    let results = inspect(&token.seal().unwrap());
+    assert!(results.ensure_success().is_err());
+    assert!(results.token.as_ref().unwrap().sealed);
+}
+
+#[test]
+fn external_key_mismatches() {
+    use biscuit_auth::KeyPair;
+    use ExternalKeyMismatch::*;
+
+    let (expected_key, other_key) = (KeyPair::new().public(), KeyPair::new().public());
+    // authority, third-party block signed with the expected key, first-party block,
+    // third-party block signed with another key
+    let external_keys = [None, Some(expected_key), None, Some(other_key)];
+    let expect = |block: usize, key: &PublicKey| ExpectedExternalKey {
+        block,
+        key: KeyPattern::Key(*key),
+    };
+
+    let mismatches = check_external_keys(
+        &external_keys,
+        &[
+            expect(1, &expected_key),
+            expect(2, &expected_key),
+            expect(3, &expected_key),
+            expect(4, &expected_key),
+        ],
+        None,
+    );
+    assert_eq!(mismatches.len(), 3);
+    assert!(matches!(
+        &mismatches[0],
+        Unexpected {
+            block: 2,
+            key: None,
+            ..
+        }
+    ));
+    assert!(matches!(
+        &mismatches[1],
+        Unexpected { block: 3, key: Some(k), .. } if *k == other_key.to_string()
+    ));
+    assert!(matches!(&mismatches[2], MissingBlock { block: 4, .. }));
+
+    let fingerprint = KeyPattern::Fingerprint(crate::keys::fingerprint(&expected_key));
+    let mismatches = check_external_keys(&external_keys, &[], Some(&[fingerprint]));
+    assert_eq!(mismatches.len(), 1);
+    assert!(matches!(
+        &mismatches[0],
+        Untrusted { block: 3, key } if *key == other_key.to_string()
+    ));
+}
+
+#[test]
+fn external_keys_need_valid_signatures() {
+    use biscuit_auth::{Biscuit, BlockBuilder, KeyPair};
+    use clap::Parser;
+
+    let (root, other_root, external) = (KeyPair::new(), KeyPair::new(), KeyPair::new());
+    let biscuit = Biscuit::builder()
+        .fact("user(\"1234\")")
+        .unwrap()
+        .build(&root)
+        .unwrap();
+    let block = biscuit
+        .third_party_request()
+        .unwrap()
+        .create_block(
+            &external.private(),
+            BlockBuilder::new().fact("group(\"admin\")").unwrap(),
+        )
+        .unwrap();
+    let biscuit = biscuit
+        .append_third_party(external.public(), block)
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let biscuit_file = dir.path().join("biscuit");
+    fs::write(&biscuit_file, biscuit.to_base64().unwrap()).unwrap();
+
+    let inspect = |public_key: Option<&PublicKey>| {
+        let public_key = public_key.map(|key| key.to_string());
+        let expected = format!("1={}", external.public());
+        let mut args = vec!["inspect", "--expect-external-key", &expected];
+        if let Some(key) = &public_key {
+            args.extend(["--public-key", key]);
+        }
+        args.push(biscuit_file.to_str().unwrap());
+        handle_inspect_inner(&Inspect::parse_from(args))
+    };
+
+    let res = inspect(Some(&root.public())).unwrap();
+    assert_eq!(res.signatures_check, Some(true));
+    assert!(matches!(&res.external_keys_check, Some(m) if m.is_empty()));
+
+    // the external keys of a token which was not signed with the root key can't be trusted
+    let res = inspect(Some(&other_root.public())).unwrap();
+    assert_eq!(res.signatures_check, Some(false));
+    assert!(res.external_keys_check.is_none());
+    assert!(res.ensure_success().is_err());
+
+    let err = inspect(None).err().unwrap();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(MissingPublicKeyForExternalKeys)
+    ));
+}
@@ -0,0 +1,348 @@
+/*
+ * SPDX-FileCopyrightText: 2021 Clément Delafargue <clement@delafargue.name>
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+use biscuit_auth::error::{FailedCheck, Logic, MatchedPolicy, Token};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use std::fmt::Write;
+
+use super::*;
+use crate::optimize::expiration;
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum ReportFormat {
+    /// Self-contained HTML document
+    #[default]
+    Html,
+}
+
+const STYLE: &str = "
+body { font-family: sans-serif; max-width: 60em; margin: 2em auto; color: #222; }
+h1, h2 { border-bottom: 1px solid #ccc; }
+pre { background: #f6f8fa; padding: 1em; overflow-x: auto; }
+code { font-family: monospace; word-break: break-all; }
+dt { font-weight: bold; }
+.ok { color: #1a7f37; }
+.ko { color: #cf222e; }
+.skip { color: #6e7781; }
+.k { color: #8250df; font-weight: bold; }
+.p { color: #0550ae; }
+.v { color: #953800; }
+.s { color: #0a3069; }
+.n { color: #116329; }
+.c { color: #6e7781; font-style: italic; }
+";
+
+const KEYWORDS: [&str; 9] = [
+    "check", "if", "all", "reject", "allow", "deny", "or", "trusting", "true",
+];
+
+impl InspectionResults {
+    /// Renders the inspection results as a self-contained HTML document
+    pub fn to_html(&self) -> String {
+        let mut out = String::new();
+        // writing to a String cannot fail
+        let _ = self.write_html(&mut out);
+        out
+    }
+
+    fn write_html(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "<!DOCTYPE html>")?;
+        writeln!(out, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+        writeln!(out, "<title>Biscuit inspection report</title>")?;
+        writeln!(out, "<style>{}</style>\n</head>\n<body>", STYLE)?;
+        writeln!(out, "<h1>Biscuit inspection report</h1>")?;
+        writeln!(
+            out,
+            "<p class=\"skip\">Generated on {}</p>",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+        )?;
+
+        writeln!(out, "<h2>Token</h2>\n<dl>")?;
+        let sealed = if self.token.sealed { "sealed" } else { "open" };
+        writeln!(out, "<dt>State</dt><dd>{}</dd>", sealed)?;
+        if let Some(root_key_id) = self.token.root_key_id {
+            writeln!(out, "<dt>Root key identifier</dt><dd>{}</dd>", root_key_id)?;
+        }
+        let expiry = match self.token.expiration() {
+            Some(date) => escape(&date),
+            None => "no expiration check".to_string(),
+        };
+        writeln!(out, "<dt>Expiration</dt><dd>{}</dd>\n</dl>", expiry)?;
+
+        for (i, block) in self.token.blocks.iter().enumerate() {
+            let title = match (i, &block.external_key) {
+                (0, _) => "Authority block".to_string(),
+                (_, Some(key)) => format!(
+                    "Block n°{} (third party, signed by <code>{}</code>, fingerprint <code>{}</code>)",
+                    i,
+                    escape(key),
+                    escape(block.external_key_fingerprint.as_deref().unwrap_or_default())
+                ),
+                (_, None) => format!("Block n°{}", i),
+            };
+            writeln!(out, "<h3>{}</h3>", title)?;
+            writeln!(
+                out,
+                "<p>Datalog {}</p>\n<pre><code>{}</code></pre>",
+                get_version_string(block.version),
+                highlight(&block.code)
+            )?;
+            writeln!(
+                out,
+                "<p>Revocation id: <code>{}</code></p>",
+                escape(&block.revocation_id)
+            )?;
+        }
+
+        if let Some(size) = &self.size {
+            writeln!(
+                out,
+                "<h2>Size</h2>\n<pre>{}</pre>",
+                escape(&size.to_string())
+            )?;
+        }
+
+        if let Some(trust_scopes) = &self.trust_scopes {
+            writeln!(
+                out,
+                "<h2>Trust scopes</h2>\n<pre>{}</pre>",
+                escape(&trust_scopes.to_string())
+            )?;
+        }
+
+        writeln!(out, "<h2>Signatures</h2>")?;
+        match self.signatures_check {
+            None => writeln!(out, "<p class=\"skip\">Public key check skipped</p>")?,
+            Some(true) => writeln!(out, "<p class=\"ok\">Public key check succeeded</p>")?,
+            Some(false) => writeln!(out, "<p class=\"ko\">Public key check failed</p>")?,
+        }
+
+        match &self.external_keys_check {
+            None => {}
+            Some(mismatches) if mismatches.is_empty() => {
+                writeln!(out, "<p class=\"ok\">External keys check succeeded</p>")?
+            }
+            Some(mismatches) => {
+                writeln!(out, "<p class=\"ko\">External keys check failed</p>\n<ul>")?;
+                for mismatch in mismatches {
+                    writeln!(out, "<li>{}</li>", escape(&mismatch.to_string()))?;
+                }
+                writeln!(out, "</ul>")?;
+            }
+        }
+
+        writeln!(out, "<h2>Authorization</h2>")?;
+        match &self.auth {
+            None => writeln!(out, "<p class=\"skip\">Datalog check skipped</p>")?,
+            Some(auth) => auth.write_html(out)?,
+        }
+
+        if let Some(query) = &self.query {
+            query.write_html(out)?;
+        }
+
+        writeln!(out, "</body>\n</html>")
+    }
+}
+
+impl TokenDescription {
+    /// The earliest date set by an expiration check, in any block
+    fn expiration(&self) -> Option<String> {
+        self.blocks
+            .iter()
+            .filter_map(|b| BlockCode::parse(&b.code).ok())
+            .flat_map(|code| {
+                code.checks
+                    .iter()
+                    .filter_map(expiration)
+                    .collect::<Vec<_>>()
+            })
+            .min()
+            .and_then(|date| DateTime::<Utc>::from_timestamp(date as i64, 0))
+            .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true))
+    }
+}
+
+impl AuthResult {
+    fn write_html(&self, out: &mut String) -> std::fmt::Result {
+        let timing = format!(
+            "{}μs, {} iterations",
+            self.elapsed_micros
+                .map(|e| e.to_string())
+                .unwrap_or("n/a".to_string()),
+            self.iterations
+        );
+        match &self.result {
+            RResult::Ok((_, policy)) => {
+                writeln!(
+                    out,
+                    "<p class=\"ok\">Authorizer check succeeded ({})</p>",
+                    timing
+                )?;
+                writeln!(
+                    out,
+                    "<p>Matched allow policy:</p>\n<pre><code>{}</code></pre>",
+                    highlight(policy)
+                )
+            }
+            RResult::Err { error } => {
+                writeln!(
+                    out,
+                    "<p class=\"ko\">Authorizer check failed ({})</p>",
+                    timing
+                )?;
+                match error {
+                    Token::FailedLogic(Logic::Unauthorized { policy, checks }) => {
+                        let (kind, i) = match policy {
+                            MatchedPolicy::Allow(i) => ("An allow", i),
+                            MatchedPolicy::Deny(i) => ("A deny", i),
+                        };
+                        writeln!(
+                            out,
+                            "<p>{} policy matched:</p>\n<pre><code>{}</code></pre>",
+                            kind,
+                            highlight(self.policies.get(*i).expect("Incorrect policy index"))
+                        )?;
+                        write_failed_checks(out, checks)
+                    }
+                    Token::FailedLogic(Logic::NoMatchingPolicy { checks }) => {
+                        writeln!(out, "<p>No policy matched</p>")?;
+                        write_failed_checks(out, checks)
+                    }
+                    e => writeln!(out, "<p>{}</p>", escape(&e.to_string())),
+                }
+            }
+        }
+    }
+}
+
+fn write_failed_checks(out: &mut String, checks: &[FailedCheck]) -> std::fmt::Result {
+    if checks.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "<p>The following checks failed:</p>\n<ul>")?;
+    for c in checks {
+        let (origin, rule) = match c {
+            FailedCheck::Block(bc) if bc.block_id == 0 => ("Authority block".to_string(), &bc.rule),
+            FailedCheck::Block(bc) => (format!("Block {}", bc.block_id), &bc.rule),
+            FailedCheck::Authorizer(ac) => ("Authorizer".to_string(), &ac.rule),
+        };
+        writeln!(out, "<li>{}: <code>{}</code></li>", origin, highlight(rule))?;
+    }
+    writeln!(out, "</ul>")
+}
+
+impl QueryResult {
+    fn write_html(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "<h2>Query</h2>")?;
+        let scope = if self.query_all { " on all facts" } else { "" };
+        writeln!(
+            out,
+            "<p>Query{} ({}μs):</p>\n<pre><code>{}</code></pre>",
+            scope,
+            self.elapsed_micros,
+            highlight(&self.query)
+        )?;
+        match &self.facts {
+            RResult::Ok(facts) if facts.is_empty() => {
+                writeln!(out, "<p class=\"ko\">No results</p>")
+            }
+            RResult::Ok(facts) => {
+                let facts = facts.iter().map(|f| highlight(f)).collect::<Vec<_>>();
+                writeln!(out, "<pre><code>{}</code></pre>", facts.join("\n"))
+            }
+            RResult::Err { error } => writeln!(
+                out,
+                "<p class=\"ko\">Query failed: {}</p>",
+                escape(&error.to_string())
+            ),
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn span(class: &str, s: &str) -> String {
+    format!("<span class=\"{}\">{}</span>", class, escape(s))
+}
+
+/// Minimal datalog syntax highlighting: keywords, predicates, variables, strings,
+/// numbers and dates, and comments
+fn highlight(code: &str) -> String {
+    let chars = code.chars().collect::<Vec<_>>();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        if c == '"' {
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i = (i + 1).min(chars.len());
+            out.push_str(&span("s", &chars[start..i].iter().collect::<String>()));
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            out.push_str(&span("c", &chars[start..i].iter().collect::<String>()));
+        } else if c == '$' || c.is_alphabetic() || c == '_' {
+            i += 1;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == ':')
+            {
+                i += 1;
+            }
+            let word = chars[start..i].iter().collect::<String>();
+            let class = if c == '$' {
+                Some("v")
+            } else if KEYWORDS.contains(&word.as_str()) {
+                Some("k")
+            } else if chars.get(i) == Some(&'(') {
+                Some("p")
+            } else {
+                None
+            };
+            match class {
+                Some(class) => out.push_str(&span(class, &word)),
+                None => out.push_str(&escape(&word)),
+            }
+        } else if c.is_ascii_digit() {
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || "-:+.".contains(chars[i]))
+                && (chars[i] != '-' || chars[i - 1].is_ascii_digit())
+            {
+                i += 1;
+            }
+            out.push_str(&span("n", &chars[start..i].iter().collect::<String>()));
+        } else {
+            i += 1;
+            out.push_str(&escape(&c.to_string()));
+        }
+    }
+    out
+}
+
+#[test]
+fn highlight_datalog() {
+    assert_eq!(
+        highlight("check if time($t), $t <= 2030-01-01T00:00:00Z"),
+        "<span class=\"k\">check</span> <span class=\"k\">if</span> <span class=\"p\">time</span>(<span class=\"v\">$t</span>), <span class=\"v\">$t</span> &lt;= <span class=\"n\">2030-01-01T00:00:00Z</span>"
+    );
+    assert_eq!(
+        highlight("user(\"<a>\")"),
+        "<span class=\"p\">user</span>(<span class=\"s\">&quot;&lt;a&gt;&quot;</span>)"
+    );
+}
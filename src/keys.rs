@@ -0,0 +1,301 @@
+/*
+ * SPDX-FileCopyrightText: 2021 Clément Delafargue <clement@delafargue.name>
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+use anyhow::{bail, Result};
+use biscuit_auth::{format::schema, PrivateKey, PublicKey};
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use std::fmt::Display;
+use std::fs;
+use std::str::FromStr;
+
+use crate::errors::CliError::*;
+use crate::input::*;
+
+/// Number of bytes of the SHA-256 hash kept in fingerprints
+const FINGERPRINT_BYTES: usize = 16;
+
+fn algorithm_name(key: &PublicKey) -> &'static str {
+    match key.algorithm() {
+        schema::public_key::Algorithm::Ed25519 => "ed25519",
+        schema::public_key::Algorithm::Secp256r1 => "secp256r1",
+    }
+}
+
+/// A short, stable identifier for a public key: the algorithm, followed by the
+/// truncated SHA-256 hash of the key bytes, eg `ed25519:sha256:-COqoRd_uqi-4cAYvDkmlg`
+pub fn fingerprint(key: &PublicKey) -> String {
+    let hash = Sha256::digest(key.to_bytes());
+    format!(
+        "{}:sha256:{}",
+        algorithm_name(key),
+        base64::encode_config(&hash[..FINGERPRINT_BYTES], base64::URL_SAFE_NO_PAD)
+    )
+}
+
+/// A public key, or the fingerprint of a public key
+#[derive(Debug, Clone)]
+pub enum KeyPattern {
+    Key(PublicKey),
+    Fingerprint(String),
+}
+
+impl KeyPattern {
+    pub fn matches(&self, key: &PublicKey) -> bool {
+        match self {
+            KeyPattern::Key(k) => k == key,
+            KeyPattern::Fingerprint(f) => *f == fingerprint(key),
+        }
+    }
+}
+
+impl FromStr for KeyPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(":sha256:") {
+            Some((algorithm, hash)) => {
+                let valid_hash = base64::decode_config(hash, base64::URL_SAFE_NO_PAD)
+                    .is_ok_and(|bytes| bytes.len() == FINGERPRINT_BYTES);
+                if !["ed25519", "secp256r1"].contains(&algorithm) || !valid_hash {
+                    return Err(format!("invalid fingerprint `{}`", s));
+                }
+                Ok(KeyPattern::Fingerprint(s.to_string()))
+            }
+            None => s
+                .parse()
+                .map(KeyPattern::Key)
+                .map_err(|e: biscuit_auth::error::Format| e.to_string()),
+        }
+    }
+}
+
+impl Display for KeyPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyPattern::Key(key) => key.fmt(f),
+            KeyPattern::Fingerprint(fingerprint) => fingerprint.fmt(f),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug, ValueEnum)]
+pub enum KeyKind {
+    Private,
+    Public,
+}
+
+/// A key read by `key convert` or `key info`, which can be private or public
+pub enum AnyKey {
+    Private(PrivateKey),
+    Public(PublicKey),
+}
+
+impl AnyKey {
+    pub fn kind(&self) -> KeyKind {
+        match self {
+            AnyKey::Private(_) => KeyKind::Private,
+            AnyKey::Public(_) => KeyKind::Public,
+        }
+    }
+
+    pub fn public(&self) -> PublicKey {
+        match self {
+            AnyKey::Private(key) => key.public(),
+            AnyKey::Public(key) => *key,
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        Algorithm(self.public().algorithm().into())
+    }
+
+    /// Encodes the key in the given format, with the algorithm prefix for hex keys
+    pub fn encode(&self, format: KeyFormat) -> Result<Vec<u8>> {
+        Ok(match (self, format) {
+            (AnyKey::Private(key), KeyFormat::Raw) => key.to_bytes().to_vec(),
+            (AnyKey::Private(key), KeyFormat::Hex) => {
+                format!("{}\n", key.to_prefixed_string()).into_bytes()
+            }
+            (AnyKey::Private(key), KeyFormat::Pem) => key.to_pem()?.as_bytes().to_vec(),
+            (AnyKey::Public(key), KeyFormat::Raw) => key.to_bytes().to_vec(),
+            (AnyKey::Public(key), KeyFormat::Hex) => format!("{}\n", key).into_bytes(),
+            (AnyKey::Public(key), KeyFormat::Pem) => key.to_pem()?.into_bytes(),
+        })
+    }
+}
+
+fn key_format_name(format: KeyFormat) -> String {
+    format
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Reads the contents of a key input, without decoding them
+fn read_key_contents(from: &KeyBytes) -> Result<(KeyFormat, Vec<u8>)> {
+    Ok(match from {
+        KeyBytes::FromStdin(KeyFormat::Raw) => (KeyFormat::Raw, read_stdin_bytes()?),
+        KeyBytes::FromStdin(format) => (*format, read_stdin_string("key")?.into_bytes()),
+        KeyBytes::FromFile(format, path) => (
+            *format,
+            fs::read(path).map_err(|_| FileNotFound(path.clone()))?,
+        ),
+        KeyBytes::FromEnv(KeyFormat::Raw, _) => Err(BinaryFromEnv)?,
+        KeyBytes::FromEnv(format, var) => (*format, read_env_string(var)?.into_bytes()),
+        KeyBytes::FromFd(format, fd) => (*format, read_fd_bytes(*fd)?),
+        KeyBytes::HexString(str) => (KeyFormat::Hex, str.clone().into_bytes()),
+        KeyBytes::PemString(str) => (KeyFormat::Pem, str.clone().into_bytes()),
+    })
+}
+
+fn decode_key(
+    contents: &[u8],
+    format: KeyFormat,
+    alg: &Option<Algorithm>,
+    kind: KeyKind,
+) -> Result<AnyKey> {
+    let text = || {
+        std::str::from_utf8(contents)
+            .map(str::trim)
+            .map_err(|e| ParseError("key".to_string(), e.to_string()))
+    };
+    Ok(match (format, kind, alg) {
+        (KeyFormat::Raw, KeyKind::Private, Some(alg)) => {
+            AnyKey::Private(PrivateKey::from_bytes(contents, alg.0)?)
+        }
+        (KeyFormat::Raw, KeyKind::Public, Some(alg)) => {
+            AnyKey::Public(PublicKey::from_bytes(contents, alg.0)?)
+        }
+        // checked by `read_any_key`
+        (KeyFormat::Raw, _, None) => unreachable!(),
+        (KeyFormat::Hex, KeyKind::Private, _) => AnyKey::Private(text()?.parse()?),
+        (KeyFormat::Hex, KeyKind::Public, _) => AnyKey::Public(text()?.parse()?),
+        (KeyFormat::Pem, KeyKind::Private, _) => AnyKey::Private(PrivateKey::from_pem(text()?)?),
+        (KeyFormat::Pem, KeyKind::Public, _) => AnyKey::Public(PublicKey::from_pem(text()?)?),
+    })
+}
+
+/// Reads a private or public key. When `detect_format` is set, text inputs can be either hex or
+/// PEM. Without an explicit kind, ed25519 hex keys are read as public keys, since private and
+/// public keys look the same. Other keys are decoded as both a private and a public key, which
+/// is ambiguous for raw ed25519 keys.
+pub fn read_any_key(
+    from: &KeyBytes,
+    detect_format: bool,
+    alg: &Option<Algorithm>,
+    kind: Option<KeyKind>,
+) -> Result<(KeyFormat, AnyKey)> {
+    let (mut format, contents) = read_key_contents(from)?;
+    let start = contents.iter().position(|b| !b.is_ascii_whitespace());
+    let text = &contents[start.unwrap_or(0)..];
+    if detect_format && format != KeyFormat::Raw {
+        format = if text.starts_with(b"-----BEGIN") {
+            KeyFormat::Pem
+        } else {
+            KeyFormat::Hex
+        };
+    }
+
+    if format == KeyFormat::Raw && alg.is_none() {
+        bail!("Raw key binary input requires an explicit key algorithm")
+    }
+
+    let kind = match kind {
+        None if format == KeyFormat::Hex && text.starts_with(b"ed25519/") => Some(KeyKind::Public),
+        kind => kind,
+    };
+    let key = match kind {
+        Some(kind) => decode_key(&contents, format, alg, kind)
+            .map_err(|e| ParseError(format!("{} key", key_format_name(format)), e.to_string()))?,
+        None => match (
+            decode_key(&contents, format, alg, KeyKind::Private),
+            decode_key(&contents, format, alg, KeyKind::Public),
+        ) {
+            (Ok(key), Err(_)) | (Err(_), Ok(key)) => key,
+            (Ok(_), Ok(_)) => Err(AmbiguousKeyKind)?,
+            (Err(e), Err(_)) => Err(ParseError(
+                format!("{} key", key_format_name(format)),
+                e.to_string(),
+            ))?,
+        },
+    };
+
+    if let Some(a) = alg {
+        if *a != key.algorithm() {
+            Err(InconsistentKeyAlgorithm(
+                key.algorithm().to_string(),
+                a.to_string(),
+            ))?
+        }
+    }
+    Ok((format, key))
+}
+
+/// Describes a key, without displaying private key material
+pub fn key_info(format: KeyFormat, key: &AnyKey) -> String {
+    let kind = match key.kind() {
+        KeyKind::Private => "private",
+        KeyKind::Public => "public",
+    };
+    format!(
+        "Algorithm: {}\nFormat: {}\nKind: {} key\nPublic key: {}\nPublic key fingerprint: {}",
+        key.algorithm(),
+        key_format_name(format),
+        kind,
+        key.public(),
+        fingerprint(&key.public())
+    )
+}
+
+#[test]
+fn key_fingerprints() {
+    let key: PublicKey = "ed25519/07955ee5c68dbcf614142ddcecea962b91cd69d477f0ad0f8a15f28eba264f67"
+        .parse()
+        .unwrap();
+    let fp = fingerprint(&key);
+    assert_eq!(fp, "ed25519:sha256:-COqoRd_uqi-4cAYvDkmlg");
+
+    let pattern: KeyPattern = fp.parse().unwrap();
+    assert!(pattern.matches(&key));
+    assert!(!pattern.matches(&biscuit_auth::KeyPair::new().public()));
+    assert!("ed25519:sha256:tooshort".parse::<KeyPattern>().is_err());
+    assert!(key.to_string().parse::<KeyPattern>().unwrap().matches(&key));
+}
+
+#[test]
+fn any_key_kinds() {
+    let key_pair = biscuit_auth::KeyPair::new_with_algorithm(biscuit_auth::Algorithm::Secp256r1);
+    let private = KeyBytes::HexString(key_pair.private().to_prefixed_string());
+    let public = KeyBytes::PemString(key_pair.public().to_pem().unwrap());
+
+    // secp256r1 private and public keys have different sizes
+    let (format, key) = read_any_key(&private, true, &None, None).unwrap();
+    assert_eq!((format, key.kind()), (KeyFormat::Hex, KeyKind::Private));
+    assert_eq!(key.public(), key_pair.public());
+    let (format, key) = read_any_key(&public, true, &None, None).unwrap();
+    assert_eq!((format, key.kind()), (KeyFormat::Pem, KeyKind::Public));
+    assert_eq!(key.public(), key_pair.public());
+
+    // ed25519 private and public hex keys look the same, and default to public keys
+    let ed25519 = biscuit_auth::KeyPair::new();
+    let hex = KeyBytes::HexString(ed25519.public().to_string());
+    let (_, key) = read_any_key(&hex, true, &None, None).unwrap();
+    assert_eq!(key.kind(), KeyKind::Public);
+    assert_eq!(key.public(), ed25519.public());
+    let hex = KeyBytes::HexString(ed25519.private().to_prefixed_string());
+    let (_, key) = read_any_key(&hex, true, &None, Some(KeyKind::Private)).unwrap();
+    assert_eq!(key.kind(), KeyKind::Private);
+    assert_eq!(key.public(), ed25519.public());
+
+    let secp256r1 = Some(Algorithm(biscuit_auth::Algorithm::Secp256r1));
+    let mismatch = read_any_key(&hex, true, &secp256r1, Some(KeyKind::Private))
+        .err()
+        .unwrap();
+    assert!(matches!(
+        mismatch.downcast_ref::<crate::errors::CliError>(),
+        Some(InconsistentKeyAlgorithm(..))
+    ));
+}
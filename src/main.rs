@@ -7,29 +7,48 @@ use anyhow::{bail, Result};
 use biscuit_auth::{
     builder::BlockBuilder,
     builder_ext::BuilderExt,
-    Biscuit, {KeyPair, PrivateKey},
+    Biscuit, UnverifiedBiscuit, {KeyPair, PrivateKey},
 };
-use clap::Parser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
 use std::io;
 use std::io::Write;
 use std::path::PathBuf;
 
 mod cli;
 mod errors;
+mod graph;
 mod input;
 mod inspect;
+mod keys;
+mod optimize;
+mod output;
+mod policy;
+mod scopes;
+mod sign_server;
+mod size;
+mod template;
+mod third_party;
 
 use cli::*;
 use input::*;
 use inspect::*;
+use optimize::*;
+use output::*;
+use sign_server::*;
+use template::*;
 
-fn handle_command(cmd: &SubCommand) -> Result<()> {
+fn handle_command(cmd: &SubCommand, matches: &ArgMatches) -> Result<()> {
     match cmd {
         SubCommand::KeyPairCmd(key_pair_cmd) => handle_keypair(key_pair_cmd),
         SubCommand::Inspect(inspect) => handle_inspect(inspect),
         SubCommand::InspectSnapshot(inspect_snapshot) => handle_inspect_snapshot(inspect_snapshot),
         SubCommand::Generate(generate) => handle_generate(generate),
-        SubCommand::Attenuate(attenuate) => handle_attenuate(attenuate),
+        SubCommand::Attenuate(attenuate) => handle_attenuate(
+            attenuate,
+            matches
+                .subcommand_matches("attenuate")
+                .expect("Missing attenuate arguments"),
+        ),
         SubCommand::GenerateThirdPartyBlockRequest(generate_request) => {
             handle_generate_request(generate_request)
         }
@@ -40,6 +59,16 @@ fn handle_command(cmd: &SubCommand) -> Result<()> {
             handle_append_third_party_block(append_third_party_block)
         }
         SubCommand::Seal(seal) => handle_seal(seal),
+        SubCommand::CheckAttenuation(check) => handle_check_attenuation(check),
+        SubCommand::Template(template_cmd) => handle_template(template_cmd),
+        SubCommand::Key(key_cmd) => handle_key(key_cmd),
+        SubCommand::Optimize(optimize) => handle_optimize(optimize),
+        SubCommand::InspectRequest(inspect_request) => handle_inspect_request(inspect_request),
+        SubCommand::InspectThirdPartyBlock(inspect_block) => {
+            handle_inspect_third_party_block(inspect_block)
+        }
+        SubCommand::ThirdPartySignServer(sign_server) => handle_sign_server(sign_server),
+        SubCommand::Attest(attest) => handle_attest(attest),
     }
 }
 
@@ -56,7 +85,7 @@ fn handle_keypair(key_pair_cmd: &KeyPairCmd) -> Result<()> {
         (Some(str), None, KeyFormat::Hex) => Some(KeyBytes::HexString(str.to_owned())),
         (Some(str), None, KeyFormat::Pem) => Some(KeyBytes::PemString(str.to_owned())),
         (None, Some(path), f) if path == &stdin_path => Some(KeyBytes::FromStdin(*f)),
-        (None, Some(path), f) => Some(KeyBytes::FromFile(*f, path.to_path_buf())),
+        (None, Some(path), f) => Some(key_bytes_from_path(path, *f)),
         (None, None, _) => None,
         // the other combinations are prevented by clap
         _ => unreachable!(),
@@ -90,6 +119,10 @@ fn handle_keypair(key_pair_cmd: &KeyPairCmd) -> Result<()> {
             }
             println!("Private key: {}", key_pair.private().to_prefixed_string());
             println!("Public key: {}", key_pair.public());
+            println!(
+                "Public key fingerprint: {}",
+                keys::fingerprint(&key_pair.public())
+            );
         }
         (false, false, KeyFormat::Pem) => {
             if private_key_from.is_some() {
@@ -102,6 +135,10 @@ fn handle_keypair(key_pair_cmd: &KeyPairCmd) -> Result<()> {
                 *key_pair.private().to_pem()?,
                 key_pair.public().to_pem()?
             );
+            println!(
+                "Public key fingerprint: {}",
+                keys::fingerprint(&key_pair.public())
+            );
         }
         (true, false, KeyFormat::Raw) => {
             let _ = io::stdout().write_all(&key_pair.private().to_bytes());
@@ -128,133 +165,137 @@ fn handle_keypair(key_pair_cmd: &KeyPairCmd) -> Result<()> {
 }
 
 fn handle_generate(generate: &Generate) -> Result<()> {
-    let authority_from = match &generate.authority_file {
-        Some(path) if path == &PathBuf::from("-") => DatalogInput::FromStdin,
-        Some(path) => DatalogInput::FromFile(path.to_path_buf()),
-        None => DatalogInput::FromEditor,
+    let template = generate
+        .template
+        .as_ref()
+        .map(|path| read_template(path))
+        .transpose()?;
+
+    let authority_from = match (&template, &generate.authority_file) {
+        (Some(template), _) => DatalogInput::DatalogString(template.authority.clone()),
+        (None, Some(path)) if path == &PathBuf::from("-") => DatalogInput::FromStdin,
+        (None, Some(path)) => DatalogInput::FromFile(path.to_path_buf()),
+        (None, None) => DatalogInput::FromEditor,
     };
 
-    let private_key: Result<PrivateKey> = read_private_key_from(
-        &match (
-            &generate.private_key_args.private_key,
-            &generate.private_key_args.private_key_file,
-            &generate.private_key_args.private_key_format,
-        ) {
-            (Some(str), None, KeyFormat::Hex) => KeyBytes::HexString(str.to_owned()),
-            (Some(str), None, KeyFormat::Pem) => KeyBytes::PemString(str.to_owned()),
-            (None, Some(file), f) => KeyBytes::FromFile(*f, file.to_path_buf()),
-            // the other combinations are prevented by clap
-            _ => unreachable!(),
-        },
-        &generate.private_key_args.private_key_algorithm,
-    );
+    let root = KeyPair::from(&generate.private_key_args.private_key()?);
+    let mut params = generate.param_arg.params()?;
+    let mut context = generate.context.clone();
+    let mut ttl = generate.add_ttl.clone();
+    let mut root_key_id = generate.root_key_id;
+    // command-line flags take precedence over the template settings
+    if let Some(template) = &template {
+        params = template.params(&params)?;
+        context = context.or_else(|| template.context.clone());
+        ttl = ttl.or(template.parsed_ttl()?);
+        root_key_id = root_key_id.or(template.root_key_id);
+    }
 
-    let root = KeyPair::from(&private_key?);
+    let mut referenced = ParamNames::default();
     let mut builder = Biscuit::builder();
-    builder = read_authority_from(
-        &authority_from,
-        &generate.param_arg.param,
-        &generate.context,
-        builder,
-    )?;
-
-    if let Some(ttl) = &generate.add_ttl {
+    builder = read_authority_from(&authority_from, &params, &mut referenced, &context, builder)?;
+    ensure_params_used(&params, &referenced, generate.param_arg.strict_params)?;
+
+    if let Some(ttl) = &ttl {
         builder = builder.check_expiration_date(ttl.to_datetime().into());
     }
-    if let Some(root_key_id) = &generate.root_key_id {
+    if let Some(root_key_id) = &root_key_id {
         builder = builder.root_key_id(*root_key_id);
     }
     let biscuit = builder.build(&root).expect("Error building biscuit"); // todo display error
-    let encoded = if generate.raw {
-        biscuit.to_vec().expect("Error serializing token")
-    } else {
-        biscuit
-            .to_base64()
-            .expect("Error serializing token")
-            .into_bytes()
-    };
+    let encoded = encode_output(
+        biscuit.to_vec().expect("Error serializing token"),
+        &OutputFormat::from_flags(generate.raw, generate.armored),
+        TOKEN_LABEL,
+        &token_headers(biscuit.root_key_id()),
+    );
     let _ = io::stdout().write_all(&encoded);
     Ok(())
 }
 
-fn handle_attenuate(attenuate: &Attenuate) -> Result<()> {
-    let biscuit_format = if attenuate.biscuit_input_args.raw_input {
-        BiscuitFormat::RawBiscuit
-    } else {
-        BiscuitFormat::Base64Biscuit
-    };
+fn handle_attenuate(attenuate: &Attenuate, matches: &ArgMatches) -> Result<()> {
+    let biscuit_format = attenuate.biscuit_input_args.biscuit_format();
 
-    let biscuit_from = if attenuate.biscuit_input_args.biscuit_file == PathBuf::from("-") {
-        BiscuitBytes::FromStdin(biscuit_format)
-    } else {
-        BiscuitBytes::FromFile(
-            biscuit_format,
-            attenuate.biscuit_input_args.biscuit_file.clone(),
-        )
-    };
+    let biscuit_from =
+        biscuit_bytes_from_path(&attenuate.biscuit_input_args.biscuit_file, biscuit_format);
 
-    let block_from = match (
-        &attenuate.block_args.block_file,
-        &attenuate.block_args.block,
-    ) {
-        (Some(file), None) => DatalogInput::FromFile(file.to_path_buf()),
-        (None, Some(str)) => DatalogInput::DatalogString(str.to_owned()),
-        (None, None) => DatalogInput::FromEditor,
-        // the other combinations are prevented by clap
-        _ => unreachable!(),
+    let default_block = if attenuate.restriction_args.has_restrictions() {
+        DatalogInput::DatalogString(String::new())
+    } else {
+        DatalogInput::FromEditor
     };
+    let blocks = attenuate.blocks_args.blocks(matches, default_block)?;
 
-    ensure_no_input_conflict(&block_from, &biscuit_from)?;
+    for block in &blocks {
+        ensure_no_input_conflict(&block.from, &biscuit_from)?;
+    }
+    let restrictions = attenuate.restriction_args.restrictions()?;
+
+    let mut biscuit = read_biscuit_from(&biscuit_from)?;
+    let params = attenuate.param_arg.params()?;
+    let mut referenced = ParamNames::default();
+    let last = blocks.len() - 1;
+
+    for (i, block) in blocks.iter().enumerate() {
+        let mut block_builder = BlockBuilder::new();
+        block_builder = read_block_from(
+            &block.from,
+            &params,
+            &mut referenced,
+            &block.context,
+            block_builder,
+        )?;
+        // restrictions are added to the last block
+        if i == last {
+            block_builder = read_block_from(
+                &DatalogInput::DatalogString(restrictions.clone()),
+                &params,
+                &mut referenced,
+                &None,
+                block_builder,
+            )?;
+            ensure_params_used(&params, &referenced, attenuate.param_arg.strict_params)?;
+        }
 
-    let biscuit = read_biscuit_from(&biscuit_from)?;
-    let mut block_builder = BlockBuilder::new();
+        if let Some(ttl) = &block.ttl {
+            block_builder = block_builder.check_expiration_date(ttl.to_datetime().into());
+        }
 
-    block_builder = read_block_from(
-        &block_from,
-        &attenuate.param_arg.param,
-        &attenuate.block_args.context,
-        block_builder,
-    )?;
+        biscuit = biscuit.append(block_builder)?;
+    }
 
-    if let Some(ttl) = &attenuate.block_args.add_ttl {
-        block_builder = block_builder.check_expiration_date(ttl.to_datetime().into());
+    if attenuate.seal {
+        biscuit = biscuit.seal()?;
     }
 
-    let new_biscuit = biscuit.append(block_builder)?;
-    let encoded = if attenuate.raw_output {
-        new_biscuit.to_vec()?
-    } else {
-        new_biscuit.to_base64()?.into_bytes()
-    };
+    let encoded = encode_output(
+        biscuit.to_vec()?,
+        &OutputFormat::from_flags(attenuate.raw_output, attenuate.armored_output),
+        TOKEN_LABEL,
+        &token_headers(biscuit.root_key_id()),
+    );
     let _ = io::stdout().write_all(&encoded);
     Ok(())
 }
 
 fn handle_generate_request(generate_request: &GenerateThirdPartyBlockRequest) -> Result<()> {
-    let biscuit_format = if generate_request.biscuit_input_args.raw_input {
-        BiscuitFormat::RawBiscuit
-    } else {
-        BiscuitFormat::Base64Biscuit
-    };
+    let biscuit_format = generate_request.biscuit_input_args.biscuit_format();
 
-    let biscuit_from = if generate_request.biscuit_input_args.biscuit_file == PathBuf::from("-") {
-        BiscuitBytes::FromStdin(biscuit_format)
-    } else {
-        BiscuitBytes::FromFile(
-            biscuit_format,
-            generate_request.biscuit_input_args.biscuit_file.clone(),
-        )
-    };
+    let biscuit_from = biscuit_bytes_from_path(
+        &generate_request.biscuit_input_args.biscuit_file,
+        biscuit_format,
+    );
 
     let biscuit = read_biscuit_from(&biscuit_from)?;
 
     let request = biscuit.third_party_request()?;
 
-    let encoded = if generate_request.raw_output {
-        request.serialize()?
-    } else {
-        request.serialize_base64()?.into_bytes()
-    };
+    let encoded = encode_output(
+        request.serialize()?,
+        &OutputFormat::from_flags(generate_request.raw_output, generate_request.armored_output),
+        THIRD_PARTY_REQUEST_LABEL,
+        &[],
+    );
     let _ = io::stdout().write_all(&encoded);
     Ok(())
 }
@@ -262,115 +303,58 @@ fn handle_generate_request(generate_request: &GenerateThirdPartyBlockRequest) ->
 fn handle_generate_third_party_block(
     generate_third_party_block: &GenerateThirdPartyBlock,
 ) -> Result<()> {
-    let block_format = if generate_third_party_block.raw_input {
-        BiscuitFormat::RawBiscuit
-    } else {
-        BiscuitFormat::Base64Biscuit
-    };
+    let block_format = BiscuitFormat::from_flags(
+        generate_third_party_block.raw_input,
+        generate_third_party_block.base64_input,
+    );
 
-    let request_from = if generate_third_party_block.request_file == PathBuf::from("-") {
-        BiscuitBytes::FromStdin(block_format)
-    } else {
-        BiscuitBytes::FromFile(
-            block_format,
-            generate_third_party_block.request_file.clone(),
-        )
-    };
+    let request_from =
+        biscuit_bytes_from_path(&generate_third_party_block.request_file, block_format);
 
-    let block_from = match (
-        &generate_third_party_block.block_args.block_file,
-        &generate_third_party_block.block_args.block,
-    ) {
-        (Some(file), None) => DatalogInput::FromFile(file.to_path_buf()),
-        (None, Some(str)) => DatalogInput::DatalogString(str.to_owned()),
-        (None, None) => DatalogInput::FromEditor,
-        // the other combinations are prevented by clap
-        _ => unreachable!(),
-    };
+    let block_args = &generate_third_party_block.third_party_block_args;
+    let block_from = block_args.datalog_input();
 
     ensure_no_input_conflict(&block_from, &request_from)?;
 
-    let private_key: Result<PrivateKey> = read_private_key_from(
-        &match (
-            &generate_third_party_block.private_key_args.private_key,
-            &generate_third_party_block.private_key_args.private_key_file,
-            &generate_third_party_block
-                .private_key_args
-                .private_key_format,
-        ) {
-            (Some(hex_string), None, KeyFormat::Hex) => KeyBytes::HexString(hex_string.to_owned()),
-            (Some(pem_string), None, KeyFormat::Pem) => KeyBytes::PemString(pem_string.to_owned()),
-            (None, Some(file), KeyFormat::Raw) => {
-                KeyBytes::FromFile(KeyFormat::Raw, file.to_path_buf())
-            }
-            (None, Some(file), f) => KeyBytes::FromFile(*f, file.to_path_buf()),
-            // the other combinations are prevented by clap
-            _ => unreachable!(),
-        },
-        &generate_third_party_block
-            .private_key_args
-            .private_key_algorithm,
-    );
+    let private_key = generate_third_party_block.private_key_args.private_key();
 
     let request = read_request_from(&request_from)?;
 
-    let mut builder = BlockBuilder::new();
-    builder = read_block_from(
-        &block_from,
-        &generate_third_party_block.param_arg.param,
-        &generate_third_party_block.block_args.context,
-        builder,
-    )?;
-
-    if let Some(ttl) = &generate_third_party_block.block_args.add_ttl {
-        builder = builder.check_expiration_date(ttl.to_datetime().into());
-    }
+    let builder = block_args.block_builder(&block_from)?;
 
     let block = request.create_block(&private_key?, builder)?;
 
-    let encoded = if generate_third_party_block.raw_output {
-        block.serialize()?
-    } else {
-        block.serialize_base64()?.into_bytes()
-    };
+    let encoded = encode_output(
+        block.serialize()?,
+        &OutputFormat::from_flags(
+            generate_third_party_block.raw_output,
+            generate_third_party_block.armored_output,
+        ),
+        THIRD_PARTY_BLOCK_LABEL,
+        &[],
+    );
     let _ = io::stdout().write_all(&encoded);
     Ok(())
 }
 
 fn handle_append_third_party_block(append_third_party_block: &AppendThirdPartyBlock) -> Result<()> {
-    let biscuit_format = if append_third_party_block.biscuit_input_args.raw_input {
-        BiscuitFormat::RawBiscuit
-    } else {
-        BiscuitFormat::Base64Biscuit
-    };
+    let biscuit_format = append_third_party_block.biscuit_input_args.biscuit_format();
 
-    let biscuit_from =
-        if append_third_party_block.biscuit_input_args.biscuit_file == PathBuf::from("-") {
-            BiscuitBytes::FromStdin(biscuit_format)
-        } else {
-            BiscuitBytes::FromFile(
-                biscuit_format,
-                append_third_party_block
-                    .biscuit_input_args
-                    .biscuit_file
-                    .clone(),
-            )
-        };
-
-    let block_file_format = if append_third_party_block.raw_block_contents {
-        BiscuitFormat::RawBiscuit
-    } else {
-        BiscuitFormat::Base64Biscuit
-    };
+    let biscuit_from = biscuit_bytes_from_path(
+        &append_third_party_block.biscuit_input_args.biscuit_file,
+        biscuit_format,
+    );
+
+    let block_file_format = BiscuitFormat::from_flags(
+        append_third_party_block.raw_block_contents,
+        append_third_party_block.base64_block_contents,
+    );
 
     let block_from = match (
         &append_third_party_block.block_contents_file,
         &append_third_party_block.block_contents,
     ) {
-        (Some(file), None) if file == &PathBuf::from("-") => {
-            BiscuitBytes::FromStdin(block_file_format)
-        }
-        (Some(file), None) => BiscuitBytes::FromFile(block_file_format, file.to_path_buf()),
+        (Some(file), None) => biscuit_bytes_from_path(file, block_file_format),
         (None, Some(str)) => BiscuitBytes::Base64String(str.to_owned()),
         // the other combinations are prevented by clap
         _ => unreachable!(),
@@ -382,40 +366,206 @@ fn handle_append_third_party_block(append_third_party_block: &AppendThirdPartyBl
 
     let new_biscuit = append_third_party_from(&biscuit, &block_from)?;
 
-    let encoded = if append_third_party_block.raw_output {
-        new_biscuit.to_vec()?
-    } else {
-        new_biscuit.to_base64()?.into_bytes()
-    };
+    let encoded = encode_output(
+        new_biscuit.to_vec()?,
+        &OutputFormat::from_flags(
+            append_third_party_block.raw_output,
+            append_third_party_block.armored_output,
+        ),
+        TOKEN_LABEL,
+        &token_headers(new_biscuit.root_key_id()),
+    );
     let _ = io::stdout().write_all(&encoded);
     Ok(())
 }
 
+fn handle_attest(attest: &Attest) -> Result<()> {
+    if let Some(new_biscuit) = handle_attest_inner(attest)? {
+        let encoded = encode_output(
+            new_biscuit.to_vec()?,
+            &OutputFormat::from_flags(attest.raw_output, attest.armored_output),
+            TOKEN_LABEL,
+            &token_headers(new_biscuit.root_key_id()),
+        );
+        let _ = io::stdout().write_all(&encoded);
+    }
+    Ok(())
+}
+
+/// Returns the extended biscuit, or `None` when only displaying the block with `--dry-run`
+fn handle_attest_inner(attest: &Attest) -> Result<Option<UnverifiedBiscuit>> {
+    let biscuit_format = attest.biscuit_input_args.biscuit_format();
+    let biscuit_from =
+        biscuit_bytes_from_path(&attest.biscuit_input_args.biscuit_file, biscuit_format);
+    let block_from = attest.third_party_block_args.datalog_input();
+    ensure_no_input_conflict(&block_from, &biscuit_from)?;
+
+    let private_key = attest.private_key_args.private_key()?;
+
+    let biscuit = read_biscuit_from(&biscuit_from)?;
+    let builder = attest.third_party_block_args.block_builder(&block_from)?;
+
+    let request = biscuit.third_party_request()?;
+    let block = request.create_block(&private_key, builder)?.serialize()?;
+
+    if attest.dry_run {
+        let contents = third_party::ThirdPartyBlockContents::decode(&block)?;
+        let results = ThirdPartyBlockInspectionResults::for_biscuit(&contents, &biscuit, None)?;
+        println!("{}", results);
+        results.ensure_success()?;
+        return Ok(None);
+    }
+
+    Ok(Some(biscuit.append_third_party(&block)?))
+}
+
 fn handle_seal(seal: &Seal) -> Result<()> {
-    let biscuit_format = if seal.biscuit_input_args.raw_input {
-        BiscuitFormat::RawBiscuit
-    } else {
-        BiscuitFormat::Base64Biscuit
-    };
+    let biscuit_format = seal.biscuit_input_args.biscuit_format();
 
-    let biscuit_from = if seal.biscuit_input_args.biscuit_file == PathBuf::from("-") {
-        BiscuitBytes::FromStdin(biscuit_format)
-    } else {
-        BiscuitBytes::FromFile(biscuit_format, seal.biscuit_input_args.biscuit_file.clone())
-    };
+    let biscuit_from =
+        biscuit_bytes_from_path(&seal.biscuit_input_args.biscuit_file, biscuit_format);
 
     let biscuit = read_biscuit_from(&biscuit_from)?;
     let new_biscuit = biscuit.seal()?;
-    let encoded = if seal.raw_output {
-        new_biscuit.to_vec()?
-    } else {
-        new_biscuit.to_base64()?.into_bytes()
-    };
+    let encoded = encode_output(
+        new_biscuit.to_vec()?,
+        &OutputFormat::from_flags(seal.raw_output, seal.armored_output),
+        TOKEN_LABEL,
+        &token_headers(new_biscuit.root_key_id()),
+    );
     let _ = io::stdout().write_all(&encoded);
     Ok(())
 }
 
+fn handle_template(template_cmd: &TemplateCmd) -> Result<()> {
+    match &template_cmd.subcmd {
+        TemplateSubCommand::Describe(describe) => {
+            let template = read_template(&describe.template_file)?;
+            println!("{}", template);
+        }
+    }
+    Ok(())
+}
+
+fn handle_key(key_cmd: &KeyCmd) -> Result<()> {
+    match &key_cmd.subcmd {
+        KeySubCommand::Convert(convert) => {
+            let (_, key) = convert.key_args.read_key()?;
+            let key = if convert.public {
+                keys::AnyKey::Public(key.public())
+            } else {
+                key
+            };
+            let _ = io::stdout().write_all(&key.encode(convert.key_output_format)?);
+        }
+        KeySubCommand::Info(info) => {
+            let (format, key) = info.key_args.read_key()?;
+            println!("{}", keys::key_info(format, &key));
+        }
+    }
+    Ok(())
+}
+
+fn handle_optimize(optimize: &Optimize) -> Result<()> {
+    let report = match (&optimize.biscuit_file, &optimize.authority_file) {
+        (_, Some(authority_file)) => {
+            let code = if authority_file == &PathBuf::from("-") {
+                read_stdin_string("datalog program")?
+            } else {
+                std::fs::read_to_string(authority_file)
+                    .map_err(|_| errors::CliError::FileNotFound(authority_file.clone()))?
+            };
+            optimize_authority(&code)?
+        }
+        (Some(biscuit_file), None) => {
+            let biscuit_format =
+                BiscuitFormat::from_flags(optimize.raw_input, optimize.base64_input);
+            let biscuit =
+                read_biscuit_from(&biscuit_bytes_from_path(biscuit_file, biscuit_format))?;
+            analyze_token(&biscuit)?
+        }
+        (None, None) => unreachable!("enforced by clap"),
+    };
+
+    if optimize.json {
+        println!("{}", serde_json::to_string(&report)?);
+    } else if let Some(authority) = report.authority() {
+        eprint!("{}", report.suggestions());
+        print!("{}", authority);
+    } else {
+        print!("{}", report.suggestions());
+    }
+    Ok(())
+}
+
 pub fn main() -> Result<()> {
-    let opts: Opts = Opts::parse();
-    handle_command(&opts.subcmd)
+    // the raw matches are kept around to get the relative order of repeated arguments
+    let matches = Opts::command().get_matches();
+    let opts = Opts::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    handle_command(&opts.subcmd, &matches)
+}
+
+#[test]
+fn attest_third_party_exchange() {
+    use clap::Parser;
+
+    let root = KeyPair::new();
+    let external = KeyPair::new();
+    let dir = tempfile::tempdir().unwrap();
+    let biscuit = Biscuit::builder()
+        .fact("user(\"1234\")")
+        .unwrap()
+        .build(&root)
+        .unwrap();
+    let biscuit_file = dir.path().join("biscuit");
+    std::fs::write(&biscuit_file, biscuit.to_vec().unwrap()).unwrap();
+    let private_key = external.private().to_prefixed_string();
+    let attest = |dry_run: bool| {
+        let mut args = vec![
+            "attest",
+            "--private-key",
+            &private_key,
+            "--block",
+            "group(\"admin\");",
+            biscuit_file.to_str().unwrap(),
+        ];
+        if dry_run {
+            args.push("--dry-run");
+        }
+        handle_attest_inner(&Attest::parse_from(args)).unwrap()
+    };
+
+    assert!(attest(true).is_none());
+    assert_eq!(
+        std::fs::read(&biscuit_file).unwrap(),
+        biscuit.to_vec().unwrap()
+    );
+
+    // generate-third-party-block-request, generate-third-party-block and append-third-party-block
+    let block = biscuit
+        .third_party_request()
+        .unwrap()
+        .create_block(
+            &external.private(),
+            BlockBuilder::new().fact("group(\"admin\")").unwrap(),
+        )
+        .unwrap();
+    let expected = biscuit
+        .append_third_party(external.public(), block)
+        .unwrap();
+
+    let attested = attest(false).unwrap().verify(root.public()).unwrap();
+    assert_eq!(attested.block_count(), expected.block_count());
+    assert_eq!(
+        attested.external_public_keys(),
+        expected.external_public_keys()
+    );
+    assert_eq!(
+        attested.print_block_source(1).unwrap(),
+        expected.print_block_source(1).unwrap()
+    );
+    assert_eq!(
+        attested.revocation_identifiers()[0],
+        expected.revocation_identifiers()[0]
+    );
 }
@@ -0,0 +1,388 @@
+/*
+ * SPDX-FileCopyrightText: 2021 Clément Delafargue <clement@delafargue.name>
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+use anyhow::Result;
+use biscuit_auth::{
+    builder::{Binary, Check, CheckKind, Op, Term},
+    UnverifiedBiscuit,
+};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+use crate::input::*;
+
+/// Strings at least this long are reported as candidates for a shorter identifier
+const LONG_STRING: usize = 32;
+
+#[derive(Serialize, Debug)]
+struct Suggestion {
+    block: usize,
+    suggestion: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OptimizationReport {
+    suggestions: Vec<Suggestion>,
+    /// The optimized authority block, when analyzing a datalog program
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authority: Option<String>,
+}
+
+impl OptimizationReport {
+    pub fn authority(&self) -> Option<&str> {
+        self.authority.as_deref()
+    }
+
+    pub fn suggestions(&self) -> String {
+        if self.suggestions.is_empty() {
+            return "✅ No optimization found\n".to_string();
+        }
+        let mut out = String::new();
+        for s in &self.suggestions {
+            if s.block == 0 {
+                out.push_str(&format!("Authority block: {}\n", s.suggestion));
+            } else {
+                out.push_str(&format!("Block n°{}: {}\n", s.block, s.suggestion));
+            }
+        }
+        out
+    }
+}
+
+/// Items which can be removed from their block without changing authorization results,
+/// as (block, index) pairs
+#[derive(Default)]
+struct Removals {
+    facts: BTreeSet<(usize, usize)>,
+    checks: BTreeSet<(usize, usize)>,
+}
+
+/// Analyzes all the blocks of a token. Attenuation blocks cannot be rewritten, so
+/// this only reports suggestions
+pub fn analyze_token(biscuit: &UnverifiedBiscuit) -> Result<OptimizationReport> {
+    let (suggestions, _) = analyze(&token_blocks(biscuit)?);
+    Ok(OptimizationReport {
+        suggestions,
+        authority: None,
+    })
+}
+
+/// Analyzes an authority block, and removes the redundant facts and checks
+pub fn optimize_authority(code: &str) -> Result<OptimizationReport> {
+    let mut block = BlockCode::parse(code)?;
+    let (suggestions, removals) = analyze(std::slice::from_ref(&block));
+
+    block.facts = keep(block.facts, &removals.facts);
+    block.checks = keep(block.checks, &removals.checks);
+
+    Ok(OptimizationReport {
+        suggestions,
+        authority: Some(block.to_string()),
+    })
+}
+
+fn keep<T>(items: Vec<T>, removals: &BTreeSet<(usize, usize)>) -> Vec<T> {
+    items
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !removals.contains(&(0, *i)))
+        .map(|(_, item)| item)
+        .collect()
+}
+
+fn analyze(blocks: &[BlockCode]) -> (Vec<Suggestion>, Removals) {
+    let mut suggestions = vec![];
+    let mut removals = Removals::default();
+    let mut suggest =
+        |block: usize, suggestion: String| suggestions.push(Suggestion { block, suggestion });
+
+    // unless a block changes its trusted scopes, facts from the authority block are
+    // visible from it, so repeating them is useless
+    let mut facts: HashMap<String, usize> = HashMap::new();
+    for (b, block) in blocks.iter().enumerate() {
+        for (i, fact) in block.facts.iter().enumerate() {
+            let fact = fact.to_string();
+            match facts.get(&fact) {
+                Some(&first) if first == b => {
+                    suggest(b, format!("fact `{}` is defined twice", fact));
+                    removals.facts.insert((b, i));
+                }
+                Some(&0) if block.scopes.is_empty() => {
+                    suggest(
+                        b,
+                        format!("fact `{}` is already defined in the authority block", fact),
+                    );
+                    removals.facts.insert((b, i));
+                }
+                _ => {
+                    facts.entry(fact).or_insert(b);
+                }
+            }
+        }
+    }
+
+    let mut checks: HashMap<String, usize> = HashMap::new();
+    for (b, block) in blocks.iter().enumerate() {
+        for (i, check) in block.checks.iter().enumerate() {
+            let check_str = check.to_string();
+            match checks.get(&check_str) {
+                Some(&first) if first == b => {
+                    suggest(b, format!("check `{}` is present twice", check_str));
+                    removals.checks.insert((b, i));
+                }
+                Some(&0)
+                    if is_monotonic(check)
+                        && block.scopes.is_empty()
+                        && blocks[0].scopes.is_empty() =>
+                {
+                    suggest(
+                        b,
+                        format!(
+                            "check `{}` is already present in the authority block",
+                            check_str
+                        ),
+                    );
+                    removals.checks.insert((b, i));
+                }
+                _ => {
+                    checks.entry(check_str.clone()).or_insert(b);
+                }
+            }
+
+            // `check if a or b` is implied by `check if a` in the same block. Checks with the
+            // same queries in a different order are equivalent, only the first one is kept
+            let implied_by = block.checks.iter().enumerate().find(|(j, other)| {
+                *j != i
+                    && !removals.checks.contains(&(b, *j))
+                    && other.kind == CheckKind::One
+                    && check.kind == CheckKind::One
+                    && other.queries.iter().all(|q| check.queries.contains(q))
+                    && (*j < i || !check.queries.iter().all(|q| other.queries.contains(q)))
+            });
+            if let Some((_, other)) = implied_by {
+                if !removals.checks.contains(&(b, i)) {
+                    let relation = if check.queries.iter().all(|q| other.queries.contains(q)) {
+                        "equivalent to"
+                    } else {
+                        "implied by"
+                    };
+                    suggest(
+                        b,
+                        format!("check `{}` is {} `{}`", check_str, relation, other),
+                    );
+                    removals.checks.insert((b, i));
+                }
+            }
+        }
+    }
+
+    // time facts come from the authorizer, so only the earliest expiration matters
+    let expirations = blocks
+        .iter()
+        .enumerate()
+        .flat_map(|(b, block)| {
+            block.checks.iter().enumerate().filter_map(move |(i, c)| {
+                expiration_bound(c).map(|(date, inclusive)| (date, inclusive, b, i, c))
+            })
+        })
+        .collect::<Vec<_>>();
+    // at the same date, `<` is stricter than `<=`
+    if let Some(&(_, _, earliest_block, earliest_index, earliest)) = expirations
+        .iter()
+        .min_by_key(|(date, inclusive, b, i, _)| (*date, *inclusive, *b, *i))
+    {
+        for &(_, _, b, i, check) in &expirations {
+            if (b, i) != (earliest_block, earliest_index) && !removals.checks.contains(&(b, i)) {
+                let location = if earliest_block == b {
+                    "the same block".to_string()
+                } else if earliest_block == 0 {
+                    "the authority block".to_string()
+                } else {
+                    format!("block n°{}", earliest_block)
+                };
+                suggest(
+                    b,
+                    format!(
+                        "expiration check `{}` is redundant with `{}` in {}",
+                        check, earliest, location
+                    ),
+                );
+                removals.checks.insert((b, i));
+            }
+        }
+    }
+
+    let used_predicates = blocks
+        .iter()
+        .flat_map(|block| {
+            block
+                .rules
+                .iter()
+                .chain(block.checks.iter().flat_map(|c| c.queries.iter()))
+        })
+        .flat_map(|rule| rule.body.iter().map(|p| p.name.clone()))
+        .collect::<BTreeSet<_>>();
+    for (b, block) in blocks.iter().enumerate() {
+        for rule in &block.rules {
+            if !used_predicates.contains(&rule.head.name) {
+                suggest(
+                    b,
+                    format!(
+                        "rule `{}` generates `{}` facts, which are not used by the token checks and rules. It is only useful if the authorizer relies on them",
+                        rule, rule.head.name
+                    ),
+                );
+            }
+        }
+    }
+
+    let mut strings: Vec<(String, usize, usize)> = vec![];
+    for (b, block) in blocks.iter().enumerate() {
+        let mut terms = vec![];
+        block
+            .facts
+            .iter()
+            .for_each(|f| terms.extend(f.predicate.terms.iter()));
+        for rule in block
+            .rules
+            .iter()
+            .chain(block.checks.iter().flat_map(|c| c.queries.iter()))
+        {
+            terms.extend(rule.head.terms.iter());
+            rule.body.iter().for_each(|p| terms.extend(p.terms.iter()));
+            rule.expressions
+                .iter()
+                .for_each(|e| collect_op_terms(&e.ops, &mut terms));
+        }
+        let mut block_strings = vec![];
+        terms
+            .into_iter()
+            .for_each(|t| collect_strings(t, &mut block_strings));
+        for s in block_strings.into_iter().filter(|s| s.len() >= LONG_STRING) {
+            match strings.iter_mut().find(|(existing, _, _)| *existing == s) {
+                Some((_, _, count)) => *count += 1,
+                None => strings.push((s, b, 1)),
+            }
+        }
+    }
+    for (s, b, count) in strings {
+        suggest(
+            b,
+            format!(
+                "long string {:?} ({} bytes, used {} times): a shorter identifier would make the token smaller",
+                s,
+                s.len(),
+                count
+            ),
+        );
+    }
+
+    suggestions.sort_by_key(|s| s.block);
+    (suggestions, removals)
+}
+
+/// Whether a check succeeding with some facts also succeeds with more facts
+fn is_monotonic(check: &Check) -> bool {
+    check.kind == CheckKind::One && check.queries.iter().all(|q| q.scopes.is_empty())
+}
+
+/// The expiration date of `check if time($t), $t < date` checks
+pub fn expiration(check: &Check) -> Option<u64> {
+    expiration_bound(check).map(|(date, _)| date)
+}
+
+/// The expiration date of an expiration check, and whether it is inclusive (`<=`)
+fn expiration_bound(check: &Check) -> Option<(u64, bool)> {
+    if !is_monotonic(check) {
+        return None;
+    }
+    let rule = match check.queries.as_slice() {
+        [rule] => rule,
+        _ => return None,
+    };
+    match (rule.body.as_slice(), rule.expressions.as_slice()) {
+        ([time], [expression]) if time.name == "time" => {
+            match (time.terms.as_slice(), expression.ops.as_slice()) {
+                (
+                    [Term::Variable(v)],
+                    [Op::Value(Term::Variable(w)), Op::Value(Term::Date(date)), Op::Binary(op @ (Binary::LessThan | Binary::LessOrEqual))],
+                ) if v == w => Some((*date, *op == Binary::LessOrEqual)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn collect_op_terms<'a>(ops: &'a [Op], terms: &mut Vec<&'a Term>) {
+    for op in ops {
+        match op {
+            Op::Value(t) => terms.push(t),
+            Op::Closure(_, ops) => collect_op_terms(ops, terms),
+            _ => {}
+        }
+    }
+}
+
+fn collect_strings(term: &Term, strings: &mut Vec<String>) {
+    match term {
+        Term::Str(s) => strings.push(s.clone()),
+        Term::Set(set) => set.iter().for_each(|t| collect_strings(t, strings)),
+        Term::Array(array) => array.iter().for_each(|t| collect_strings(t, strings)),
+        Term::Map(map) => map.values().for_each(|t| collect_strings(t, strings)),
+        _ => {}
+    }
+}
+
+#[test]
+fn optimize_authority_block() {
+    let report = optimize_authority(
+        r#"
+        right("read");
+        right("read");
+        check if operation("read");
+        check if operation("read") or operation("write");
+        check if time($time), $time <= 2030-01-01T00:00:00Z;
+        check if time($time), $time <= 2025-01-01T00:00:00Z;
+        "#,
+    )
+    .unwrap();
+    assert_eq!(report.suggestions.len(), 3);
+    assert_eq!(
+        report.authority().unwrap(),
+        "right(\"read\");\ncheck if operation(\"read\");\ncheck if time($time), $time <= 2025-01-01T00:00:00Z;\n"
+    );
+}
+
+#[test]
+fn optimize_equivalent_checks() {
+    let report = optimize_authority(
+        r#"
+        check if a(1) or b(1);
+        check if b(1) or a(1);
+        right("x");
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        report.authority().unwrap(),
+        "right(\"x\");\ncheck if a(1) or b(1);\n"
+    );
+}
+
+#[test]
+fn optimize_strict_expiration() {
+    let report = optimize_authority(
+        r#"
+        check if time($time), $time <= 2025-01-01T00:00:00Z;
+        check if time($time), $time < 2025-01-01T00:00:00Z;
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        report.authority().unwrap(),
+        "check if time($time), $time < 2025-01-01T00:00:00Z;\n"
+    );
+}
@@ -0,0 +1,90 @@
+/*
+ * SPDX-FileCopyrightText: 2021 Clément Delafargue <clement@delafargue.name>
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+pub enum OutputFormat {
+    Raw,
+    Base64,
+    Armored,
+}
+
+impl OutputFormat {
+    pub fn from_flags(raw_output: bool, armored_output: bool) -> Self {
+        match (raw_output, armored_output) {
+            (true, _) => Self::Raw,
+            (false, true) => Self::Armored,
+            (false, false) => Self::Base64,
+        }
+    }
+}
+
+pub const TOKEN_LABEL: &str = "BISCUIT TOKEN";
+pub const SNAPSHOT_LABEL: &str = "BISCUIT SNAPSHOT";
+pub const POLICIES_SNAPSHOT_LABEL: &str = "BISCUIT POLICIES SNAPSHOT";
+pub const THIRD_PARTY_REQUEST_LABEL: &str = "BISCUIT THIRD PARTY REQUEST";
+pub const THIRD_PARTY_BLOCK_LABEL: &str = "BISCUIT THIRD PARTY BLOCK";
+
+/// Encodes serialized bytes in the requested format. Headers are only used by the armored format.
+pub fn encode_output(
+    bytes: Vec<u8>,
+    format: &OutputFormat,
+    label: &str,
+    headers: &[(&str, String)],
+) -> Vec<u8> {
+    match format {
+        OutputFormat::Raw => bytes,
+        OutputFormat::Base64 => base64::encode_config(bytes, base64::URL_SAFE).into_bytes(),
+        OutputFormat::Armored => armor(&bytes, label, headers).into_bytes(),
+    }
+}
+
+/// Wraps bytes in a PEM-style armor, with optional `Key: value` headers
+/// followed by an empty line, and base64 lines wrapped at 64 characters
+pub fn armor(bytes: &[u8], label: &str, headers: &[(&str, String)]) -> String {
+    let mut out = format!("-----BEGIN {}-----\n", label);
+    for (key, value) in headers {
+        out.push_str(&format!("{}: {}\n", key, value));
+    }
+    if !headers.is_empty() {
+        out.push('\n');
+    }
+    let encoded = base64::encode_config(bytes, base64::STANDARD);
+    for line in encoded.as_bytes().chunks(64) {
+        out.push_str(&String::from_utf8_lossy(line));
+        out.push('\n');
+    }
+    out.push_str(&format!("-----END {}-----\n", label));
+    out
+}
+
+/// Headers describing a token: its root key id, if any. They only depend on the token,
+/// so that encoding the same token twice gives the same output
+pub fn token_headers(root_key_id: Option<u32>) -> Vec<(&'static str, String)> {
+    root_key_id
+        .map(|id| ("Root-Key-Id", id.to_string()))
+        .into_iter()
+        .collect()
+}
+
+#[test]
+fn armor_roundtrip() {
+    let bytes = (0..=255).collect::<Vec<u8>>();
+    let armored = armor(&bytes, TOKEN_LABEL, &token_headers(Some(1)));
+    assert!(armored.starts_with("-----BEGIN BISCUIT TOKEN-----\nRoot-Key-Id: 1\n"));
+    assert!(armored.lines().all(|l| l.len() <= 64));
+    assert_eq!(crate::input::decode_text(&armored).unwrap(), bytes);
+}
+
+#[test]
+fn token_header_set() {
+    assert_eq!(
+        token_headers(Some(1)),
+        vec![("Root-Key-Id", "1".to_string())]
+    );
+    assert!(token_headers(None).is_empty());
+    assert_eq!(
+        armor(&[1, 2], TOKEN_LABEL, &token_headers(None)),
+        "-----BEGIN BISCUIT TOKEN-----\nAQI=\n-----END BISCUIT TOKEN-----\n"
+    );
+}
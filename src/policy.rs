@@ -0,0 +1,291 @@
+/*
+ * SPDX-FileCopyrightText: 2021 Clément Delafargue <clement@delafargue.name>
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+use anyhow::Result;
+use biscuit_auth::builder::{Check, Op, Rule, Term};
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::errors::CliError::*;
+use crate::input::*;
+use crate::optimize::expiration;
+
+/// A signing policy, read from a TOML file: the datalog a third party accepts to sign
+///
+/// ```toml
+/// # predicates the block may use, in facts, rules and checks. When a TTL is
+/// # required, `time` is allowed in expiration checks
+/// allowed_predicates = ["user", "group"]
+/// # checks the block must contain, regardless of variable names
+/// required_checks = ["check if user($user)"]
+/// # the block must expire, at most one day after it is signed
+/// require_ttl = true
+/// max_ttl = "1d"
+/// # rules are allowed, except those generating `right` facts (rules are only
+/// # restricted by their head predicate name)
+/// allow_rules = true
+/// forbidden_rule_heads = ["right"]
+/// # `trusting` annotations are rejected
+/// allow_trusting = false
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SigningPolicy {
+    allowed_predicates: Option<Vec<String>>,
+    #[serde(default)]
+    required_checks: Vec<String>,
+    #[serde(default)]
+    require_ttl: bool,
+    max_ttl: Option<String>,
+    #[serde(default = "default_true")]
+    allow_rules: bool,
+    #[serde(default)]
+    forbidden_rule_heads: Vec<String>,
+    #[serde(default = "default_true")]
+    allow_trusting: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Reads a signing policy and makes sure its checks and maximum TTL are valid
+pub fn read_signing_policy(path: &Path) -> Result<SigningPolicy> {
+    let contents = fs::read_to_string(path).map_err(|_| FileNotFound(path.to_path_buf()))?;
+    let policy: SigningPolicy = toml::from_str(&contents)
+        .map_err(|e| ParseError("signing policy".to_string(), e.to_string()))?;
+    policy.required_checks()?;
+    if let Some(max_ttl) = &policy.max_ttl {
+        parse_duration(max_ttl)?;
+    }
+    Ok(policy)
+}
+
+impl SigningPolicy {
+    fn required_checks(&self) -> Result<Vec<Check>> {
+        let code = self
+            .required_checks
+            .iter()
+            .map(|c| format!("{};\n", c.trim().trim_end_matches(';')))
+            .collect::<String>();
+        let block = BlockCode::parse(&code)?;
+        if !block.facts.is_empty() || !block.rules.is_empty() || !block.policies.is_empty() {
+            Err(ParseError(
+                "signing policy".to_string(),
+                "required_checks can only contain checks".to_string(),
+            ))?
+        }
+        Ok(block.checks)
+    }
+
+    /// Lists the parts of the block which are not allowed by the policy
+    pub fn violations(&self, code: &BlockCode) -> Result<Vec<String>> {
+        let mut violations = vec![];
+        let expirations = code
+            .checks
+            .iter()
+            .filter_map(expiration)
+            .collect::<Vec<_>>();
+
+        if let Some(allowed) = &self.allowed_predicates {
+            let ttl = self.require_ttl || self.max_ttl.is_some();
+            let mut predicates = code
+                .facts
+                .iter()
+                .map(|f| &f.predicate)
+                .chain(code.rules.iter().map(|r| &r.head))
+                .chain(code.rules.iter().flat_map(|r| r.body.iter()))
+                .map(|p| p.name.clone())
+                .collect::<Vec<_>>();
+            for check in &code.checks {
+                let is_expiration = expiration(check).is_some();
+                predicates.extend(
+                    check
+                        .queries
+                        .iter()
+                        .flat_map(|q| q.body.iter())
+                        .filter(|p| !(ttl && is_expiration && p.name == "time"))
+                        .map(|p| p.name.clone()),
+                );
+            }
+            for predicate in predicates {
+                let violation = format!("predicate `{}` is not allowed", predicate);
+                if !allowed.contains(&predicate) && !violations.contains(&violation) {
+                    violations.push(violation);
+                }
+            }
+        }
+
+        for check in self.required_checks()? {
+            let required = normalize_variables(&check);
+            if !code
+                .checks
+                .iter()
+                .any(|c| normalize_variables(c) == required)
+            {
+                violations.push(format!("check `{}` is required", check));
+            }
+        }
+
+        if self.require_ttl && self.max_ttl.is_none() && expirations.is_empty() {
+            violations.push("an expiration check is required".to_string());
+        }
+        if let Some(max_ttl) = &self.max_ttl {
+            let latest = Utc::now() + parse_duration(max_ttl)?;
+            match expirations.iter().min() {
+                None => violations.push(format!(
+                    "an expiration check is required, with a TTL of at most {}",
+                    max_ttl
+                )),
+                Some(&date) if date as i64 > latest.timestamp() => {
+                    violations.push(format!("the TTL exceeds the maximum of {}", max_ttl))
+                }
+                _ => {}
+            }
+        }
+
+        for rule in &code.rules {
+            if !self.allow_rules {
+                violations.push(format!("rule `{}` is not allowed", rule));
+            } else if self.forbidden_rule_heads.contains(&rule.head.name) {
+                violations.push(format!(
+                    "rule `{}` generates forbidden `{}` facts",
+                    rule, rule.head.name
+                ));
+            }
+        }
+
+        if !self.allow_trusting {
+            if !code.scopes.is_empty() {
+                violations.push("block-level `trusting` annotations are not allowed".to_string());
+            }
+            let queries = code
+                .rules
+                .iter()
+                .chain(code.checks.iter().flat_map(|c| c.queries.iter()));
+            for rule in queries.filter(|q: &&Rule| !q.scopes.is_empty()) {
+                violations.push(format!(
+                    "`trusting` annotation in `{}` is not allowed",
+                    rule
+                ));
+            }
+        }
+
+        Ok(violations)
+    }
+
+    /// Fails with all the violations when the block is not allowed by the policy
+    pub fn ensure_allowed(&self, code: &BlockCode) -> Result<()> {
+        let violations = self.violations(code)?;
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(PolicyViolation(violations.join(", ")))?
+        }
+    }
+}
+
+/// Renames the variables of each query in order of appearance, so that checks which only differ
+/// by their variable names are displayed the same way
+fn normalize_variables(check: &Check) -> String {
+    fn rename(name: &mut String, names: &mut HashMap<String, String>) {
+        let len = names.len();
+        *name = names
+            .entry(name.clone())
+            .or_insert_with(|| format!("v{}", len))
+            .clone();
+    }
+    fn rename_ops(ops: &mut [Op], names: &mut HashMap<String, String>) {
+        for op in ops {
+            match op {
+                Op::Value(Term::Variable(name)) => rename(name, names),
+                Op::Closure(params, ops) => {
+                    params.iter_mut().for_each(|p| rename(p, names));
+                    rename_ops(ops, names);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut check = check.clone();
+    for query in &mut check.queries {
+        let mut names = HashMap::new();
+        let predicates = std::iter::once(&mut query.head).chain(query.body.iter_mut());
+        for term in predicates.flat_map(|p| p.terms.iter_mut()) {
+            if let Term::Variable(name) = term {
+                rename(name, &mut names);
+            }
+        }
+        for expression in &mut query.expressions {
+            rename_ops(&mut expression.ops, &mut names);
+        }
+    }
+    check.to_string()
+}
+
+#[test]
+fn signing_policy_violations() {
+    let policy: SigningPolicy = toml::from_str(
+        r#"
+        allowed_predicates = ["user", "right"]
+        required_checks = ["check if user($u)"]
+        max_ttl = "1h"
+        forbidden_rule_heads = ["right"]
+        allow_trusting = false
+        "#,
+    )
+    .unwrap();
+
+    let allowed = BlockCode::parse(
+        "user(\"1234\"); check if user($id); check if time($t), $t <= 2020-01-01T00:00:00Z;",
+    )
+    .unwrap();
+    assert_eq!(policy.violations(&allowed).unwrap(), Vec::<String>::new());
+
+    let rejected = BlockCode::parse(
+        "trusting previous; group(\"admin\"); right($r) <- user($r); check if time($t), $t <= 2100-01-01T00:00:00Z;",
+    )
+    .unwrap();
+    assert_eq!(
+        policy.violations(&rejected).unwrap(),
+        vec![
+            "predicate `group` is not allowed",
+            "check `check if user($u)` is required",
+            "the TTL exceeds the maximum of 1h",
+            "rule `right($r) <- user($r)` generates forbidden `right` facts",
+            "block-level `trusting` annotations are not allowed",
+        ]
+    );
+}
+
+#[test]
+fn required_checks_ignore_variable_names() {
+    let check = |code: &str| {
+        BlockCode::parse(&format!("{};", code))
+            .unwrap()
+            .checks
+            .remove(0)
+    };
+    assert_eq!(
+        normalize_variables(&check(
+            "check if right($r, $o), [\"read\"].any($p -> $p == $o)"
+        )),
+        normalize_variables(&check(
+            "check if right($x, $y), [\"read\"].any($q -> $q == $y)"
+        ))
+    );
+    assert_ne!(
+        normalize_variables(&check("check if right($a, $a)")),
+        normalize_variables(&check("check if right($a, $b)"))
+    );
+    assert_ne!(
+        normalize_variables(&check("check if right($a, $b)")),
+        normalize_variables(&check("check all right($a, $b)"))
+    );
+}
@@ -0,0 +1,268 @@
+/*
+ * SPDX-FileCopyrightText: 2021 Clément Delafargue <clement@delafargue.name>
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+use biscuit_auth::builder::{Rule, Scope};
+use serde::Serialize;
+use std::fmt::Display;
+
+use crate::input::*;
+
+/// Blocks whose facts are trusted with the given scopes, on top of the block itself
+/// and the authorizer. `index` is the position of the block in the token, or the
+/// number of blocks for the authorizer. Without `trusting` annotations, only the
+/// authority block is trusted.
+pub fn trusted_blocks(
+    scopes: &[Scope],
+    index: usize,
+    external_keys: &[Option<String>],
+) -> Vec<usize> {
+    let mut trusted = vec![];
+    if scopes.is_empty() {
+        trusted.push(0);
+    }
+    for scope in scopes {
+        match scope {
+            Scope::Authority => trusted.push(0),
+            Scope::Previous => trusted.extend(0..index.min(external_keys.len())),
+            Scope::PublicKey(key) => {
+                let key = key.to_string();
+                trusted.extend(
+                    external_keys
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, k)| k.as_ref() == Some(&key))
+                        .map(|(i, _)| i),
+                );
+            }
+            // unresolved parameters do not match any block
+            Scope::Parameter(_) => {}
+        }
+    }
+    trusted.retain(|i| *i != index && *i < external_keys.len());
+    trusted.sort_unstable();
+    trusted.dedup();
+    trusted
+}
+
+fn block_name(index: usize) -> String {
+    if index == 0 {
+        "authority block".to_string()
+    } else {
+        format!("block n°{}", index)
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ScopedRule {
+    rule: String,
+    trusting: Vec<String>,
+    visible: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct BlockTrust {
+    block: String,
+    /// Block-level `trusting` annotation, empty for the default scope
+    trusting: Vec<String>,
+    visible: Vec<String>,
+    /// Rules, checks and policies with their own `trusting` annotation
+    rules: Vec<ScopedRule>,
+}
+
+#[derive(Serialize, Debug)]
+struct TrustedKey {
+    key: String,
+    signed_blocks: Vec<usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TrustScopes {
+    blocks: Vec<BlockTrust>,
+    authorizer: Option<BlockTrust>,
+    authorizer_keys: Vec<TrustedKey>,
+}
+
+fn visible(scopes: &[Scope], index: usize, external_keys: &[Option<String>]) -> Vec<String> {
+    let mut blocks = trusted_blocks(scopes, index, external_keys);
+    if index < external_keys.len() {
+        blocks.push(index);
+        blocks.sort_unstable();
+    }
+    blocks
+        .into_iter()
+        .map(block_name)
+        .chain(std::iter::once("authorizer".to_string()))
+        .collect()
+}
+
+fn block_trust(
+    name: String,
+    code: &BlockCode,
+    index: usize,
+    external_keys: &[Option<String>],
+) -> BlockTrust {
+    let scoped_rules = code
+        .rules
+        .iter()
+        .map(|r| (r.to_string(), &r.scopes))
+        .chain(
+            code.checks
+                .iter()
+                .flat_map(|c| c.queries.iter().map(move |q| (c.to_string(), &q.scopes))),
+        )
+        .chain(
+            code.policies
+                .iter()
+                .flat_map(|p| p.queries.iter().map(move |q| (p.to_string(), &q.scopes))),
+        )
+        .filter(|(_, scopes)| !scopes.is_empty());
+
+    let mut rules: Vec<ScopedRule> = vec![];
+    for (rule, scopes) in scoped_rules {
+        // checks and policies with several queries are displayed once per scope
+        let trusting = scopes.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        if rules
+            .iter()
+            .any(|r| r.rule == rule && r.trusting == trusting)
+        {
+            continue;
+        }
+        rules.push(ScopedRule {
+            rule,
+            trusting,
+            visible: visible(scopes, index, external_keys),
+        });
+    }
+
+    BlockTrust {
+        block: name,
+        trusting: code.scopes.iter().map(|s| s.to_string()).collect(),
+        visible: visible(&code.scopes, index, external_keys),
+        rules,
+    }
+}
+
+/// Explains which blocks each block of a token, and the authorizer, trust facts from
+pub fn trust_scopes(
+    blocks: &[BlockCode],
+    external_keys: &[Option<String>],
+    authorizer: Option<&BlockCode>,
+) -> TrustScopes {
+    let block_trusts = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, code)| {
+            let name = match (i, &external_keys[i]) {
+                (0, _) => "Authority block".to_string(),
+                (_, Some(key)) => format!("Block n°{} (third party, signed by {})", i, key),
+                (_, None) => format!("Block n°{}", i),
+            };
+            block_trust(name, code, i, external_keys)
+        })
+        .collect();
+
+    let mut authorizer_keys: Vec<TrustedKey> = vec![];
+    if let Some(authorizer) = authorizer {
+        let queries = authorizer
+            .rules
+            .iter()
+            .chain(authorizer.checks.iter().flat_map(|c| c.queries.iter()))
+            .chain(authorizer.policies.iter().flat_map(|p| p.queries.iter()));
+        let scopes = authorizer
+            .scopes
+            .iter()
+            .chain(queries.flat_map(|q: &Rule| q.scopes.iter()));
+        for scope in scopes {
+            if let Scope::PublicKey(key) = scope {
+                let key = key.to_string();
+                if authorizer_keys.iter().any(|k| k.key == key) {
+                    continue;
+                }
+                let signed_blocks = external_keys
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, k)| k.as_ref() == Some(&key))
+                    .map(|(i, _)| i)
+                    .collect();
+                authorizer_keys.push(TrustedKey { key, signed_blocks });
+            }
+        }
+    }
+
+    TrustScopes {
+        blocks: block_trusts,
+        authorizer: authorizer
+            .map(|a| block_trust("Authorizer".to_string(), a, blocks.len(), external_keys)),
+        authorizer_keys,
+    }
+}
+
+impl Display for BlockTrust {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.trusting.is_empty() {
+            writeln!(f, "{}: default scope", self.block)?;
+        } else {
+            writeln!(f, "{}: trusting {}", self.block, self.trusting.join(", "))?;
+        }
+        writeln!(f, "  sees facts from: {}", self.visible.join(", "))?;
+        for rule in &self.rules {
+            writeln!(f, "  {}", rule.rule)?;
+            writeln!(f, "    sees facts from: {}", rule.visible.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for TrustScopes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "== Trust scopes ==")?;
+        for block in &self.blocks {
+            block.fmt(f)?;
+        }
+        if let Some(authorizer) = &self.authorizer {
+            authorizer.fmt(f)?;
+            if self.authorizer_keys.is_empty() {
+                writeln!(f, "The authorizer does not trust any external key")?;
+            } else {
+                writeln!(f, "External keys trusted by the authorizer:")?;
+                for key in &self.authorizer_keys {
+                    if key.signed_blocks.is_empty() {
+                        writeln!(f, "  {}: no block is signed by this key", key.key)?;
+                    } else {
+                        let blocks = key
+                            .signed_blocks
+                            .iter()
+                            .map(|i| block_name(*i))
+                            .collect::<Vec<_>>();
+                        writeln!(f, "  {}: signed {}", key.key, blocks.join(", "))?;
+                    }
+                }
+            }
+        }
+        writeln!(f, "\n==========\n")
+    }
+}
+
+#[test]
+fn trusted_blocks_scopes() {
+    let external_key = "ed25519/07955ee5c68dbcf614142ddcecea962b91cd69d477f0ad0f8a15f28eba264f67";
+    let keys = vec![None, Some(external_key.to_string()), None];
+    let scopes = |code: &str| BlockCode::parse(code).unwrap().scopes;
+
+    assert_eq!(trusted_blocks(&[], 0, &keys), Vec::<usize>::new());
+    assert_eq!(trusted_blocks(&[], 2, &keys), vec![0]);
+    assert_eq!(
+        trusted_blocks(&scopes("trusting previous;"), 2, &keys),
+        vec![0, 1]
+    );
+    assert_eq!(
+        trusted_blocks(&scopes(&format!("trusting {};", external_key)), 2, &keys),
+        vec![1]
+    );
+    assert_eq!(
+        trusted_blocks(&scopes("trusting previous;"), 3, &keys),
+        vec![0, 1, 2]
+    );
+}
@@ -0,0 +1,235 @@
+/*
+ * SPDX-FileCopyrightText: 2021 Clément Delafargue <clement@delafargue.name>
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+use anyhow::Result;
+use biscuit_auth::{builder::BlockBuilder, builder_ext::BuilderExt, PrivateKey};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use crate::cli::*;
+use crate::errors::CliError::*;
+use crate::input::*;
+use crate::output::*;
+use crate::policy::*;
+
+/// What is needed to sign the blocks requested in the spool directory
+struct Signer {
+    private_key: PrivateKey,
+    policy: Option<SigningPolicy>,
+    ttl: Option<Ttl>,
+}
+
+impl Signer {
+    /// Signs the block described by `NAME.datalog`, for the request in `NAME.request`
+    fn sign(&self, request_file: &Path, datalog_file: &Path) -> Result<Vec<u8>> {
+        if !datalog_file.exists() {
+            Err(FileNotFound(datalog_file.to_path_buf()))?
+        }
+        let request = read_request_from(&BiscuitBytes::FromFile(
+            BiscuitFormat::AutoDetect,
+            request_file.to_path_buf(),
+        ))?;
+
+        let mut referenced = ParamNames::default();
+        let mut builder = read_block_from(
+            &DatalogInput::FromFile(datalog_file.to_path_buf()),
+            &[],
+            &mut referenced,
+            &None,
+            BlockBuilder::new(),
+        )?;
+        if let Some(ttl) = &self.ttl {
+            builder = builder.check_expiration_date(ttl.to_datetime().into());
+        }
+        if let Some(policy) = &self.policy {
+            policy.ensure_allowed(&BlockCode::from_builder(&builder)?)?;
+        }
+
+        let block = request.create_block(&self.private_key, builder)?;
+        Ok(encode_output(
+            block.serialize()?,
+            &OutputFormat::Base64,
+            THIRD_PARTY_BLOCK_LABEL,
+            &[],
+        ))
+    }
+}
+
+/// Requests waiting in the spool directory, in alphabetical order
+fn pending_requests(spool_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut requests = fs::read_dir(spool_dir)
+        .map_err(|_| FileNotFound(spool_dir.to_path_buf()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|e| e == "request"))
+        .collect::<Vec<_>>();
+    requests.sort();
+    Ok(requests)
+}
+
+/// Writes the result of a job next to it, through a temporary file so that clients
+/// never read a partial result
+fn write_result(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents).map_err(FileError)?;
+    fs::rename(&tmp, path).map_err(FileError)?;
+    Ok(())
+}
+
+/// Signs the block requested in `NAME.request`, removes the job and writes the result next to it
+fn process_job(
+    request_file: &Path,
+    datalog_file: &Path,
+    name: &str,
+    signer: &Signer,
+) -> Result<()> {
+    let result = signer.sign(request_file, datalog_file);
+    // the job is removed before its result is written, so that it is never signed twice
+    fs::remove_file(request_file).map_err(FileError)?;
+    if datalog_file.exists() {
+        fs::remove_file(datalog_file).map_err(FileError)?;
+    }
+    match result {
+        Ok(block) => {
+            write_result(&request_file.with_extension("block"), &block)?;
+            eprintln!("✅ {}: block signed", name);
+        }
+        Err(e) => {
+            write_result(
+                &request_file.with_extension("error"),
+                format!("{}\n", e).as_bytes(),
+            )?;
+            eprintln!("❌ {}: {}", name, e);
+        }
+    }
+    Ok(())
+}
+
+fn process_pending(spool_dir: &Path, signer: &Signer) -> Result<()> {
+    for request_file in pending_requests(spool_dir)? {
+        let datalog_file = request_file.with_extension("datalog");
+        let name = request_file
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        // a job that can't be completed must not stop the server: the next jobs are
+        // processed, and the failure is reported in `NAME.error` when possible. A job
+        // that could not be removed is retried, so it gets no result yet
+        if let Err(e) = process_job(&request_file, &datalog_file, &name, signer) {
+            if !request_file.exists() {
+                let _ = write_result(
+                    &request_file.with_extension("error"),
+                    format!("{}\n", e).as_bytes(),
+                );
+            }
+            eprintln!("❌ {}: {}", name, e);
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_sign_server(sign_server: &ThirdPartySignServer) -> Result<()> {
+    let private_key = sign_server.private_key_args.private_key()?;
+    let policy = match &sign_server.policy {
+        Some(path) => Some(read_signing_policy(path)?),
+        None => None,
+    };
+    let signer = Signer {
+        private_key,
+        policy,
+        ttl: sign_server.add_ttl.clone(),
+    };
+
+    if !sign_server.spool_dir.is_dir() {
+        Err(FileNotFound(sign_server.spool_dir.clone()))?
+    }
+    if !sign_server.once {
+        eprintln!(
+            "Watching {} for third-party block requests",
+            sign_server.spool_dir.display()
+        );
+    }
+    let interval = sign_server
+        .poll_interval
+        .to_std()
+        .map_err(|_| InvalidDuration)?;
+    loop {
+        process_pending(&sign_server.spool_dir, &signer)?;
+        if sign_server.once {
+            return Ok(());
+        }
+        thread::sleep(interval);
+    }
+}
+
+#[test]
+fn sign_server_once() {
+    use biscuit_auth::{Biscuit, KeyPair};
+    use clap::Parser;
+
+    let root = KeyPair::new();
+    let external = KeyPair::new();
+    let dir = tempfile::tempdir().unwrap();
+    let spool_dir = dir.path().join("spool");
+    fs::create_dir(&spool_dir).unwrap();
+    let policy_file = dir.path().join("policy.toml");
+    fs::write(&policy_file, "allowed_predicates = [\"group\"]\n").unwrap();
+
+    let biscuit = Biscuit::builder()
+        .fact("user(\"1234\")")
+        .unwrap()
+        .build(&root)
+        .unwrap();
+    let request = biscuit.third_party_request().unwrap().serialize().unwrap();
+    for (name, datalog) in [
+        ("allowed", "group(\"admin\");"),
+        ("denied", "right(\"write\");"),
+    ] {
+        fs::write(spool_dir.join(format!("{}.datalog", name)), datalog).unwrap();
+        fs::write(spool_dir.join(format!("{}.request", name)), &request).unwrap();
+    }
+    // a request still being written by a client
+    fs::write(spool_dir.join("pending.datalog"), "group(\"admin\");").unwrap();
+    fs::write(spool_dir.join("pending.request.tmp"), &request[..8]).unwrap();
+
+    let private_key = external.private().to_prefixed_string();
+    handle_sign_server(&ThirdPartySignServer::parse_from([
+        "third-party-sign-server",
+        "--private-key",
+        &private_key,
+        "--policy",
+        policy_file.to_str().unwrap(),
+        "--once",
+        spool_dir.to_str().unwrap(),
+    ]))
+    .unwrap();
+
+    let unverified = biscuit_auth::UnverifiedBiscuit::from(biscuit.to_vec().unwrap()).unwrap();
+    let extended = append_third_party_from(
+        &unverified,
+        &BiscuitBytes::FromFile(BiscuitFormat::AutoDetect, spool_dir.join("allowed.block")),
+    )
+    .unwrap()
+    .verify(root.public())
+    .unwrap();
+    assert_eq!(
+        extended.print_block_source(1).unwrap(),
+        "group(\"admin\");\n"
+    );
+    assert!(!spool_dir.join("allowed.error").exists());
+    assert!(fs::read_to_string(spool_dir.join("denied.error"))
+        .unwrap()
+        .contains("right"));
+    assert!(!spool_dir.join("denied.block").exists());
+    for name in ["allowed", "denied"] {
+        assert!(!spool_dir.join(format!("{}.request", name)).exists());
+        assert!(!spool_dir.join(format!("{}.datalog", name)).exists());
+    }
+    assert!(spool_dir.join("pending.request.tmp").exists());
+    assert!(spool_dir.join("pending.datalog").exists());
+    assert!(!spool_dir.join("pending.block").exists());
+    assert!(!spool_dir.join("pending.error").exists());
+}
@@ -0,0 +1,212 @@
+/*
+ * SPDX-FileCopyrightText: 2021 Clément Delafargue <clement@delafargue.name>
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+use anyhow::Result;
+use biscuit_auth::format::schema;
+use prost::Message;
+use serde::Serialize;
+use std::fmt::Display;
+
+use crate::errors::CliError::*;
+
+/// How many symbols are listed for each block, from the largest
+const LARGEST_SYMBOLS: usize = 3;
+
+#[derive(Serialize, Debug)]
+struct SymbolSize {
+    symbol: String,
+    bytes: usize,
+}
+
+/// A size in bytes, raw and once base64-encoded. Parts of a token are not encoded
+/// separately, so their base64 size is the size they would have if they were
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+struct Size {
+    raw: usize,
+    base64: usize,
+}
+
+impl Size {
+    fn new(raw: usize) -> Self {
+        Size {
+            raw,
+            base64: raw.div_ceil(3) * 4,
+        }
+    }
+}
+
+impl Display for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes ({} in base64)", self.raw, self.base64)
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct BlockSize {
+    /// The whole signed block, as encoded in the token
+    total: Size,
+    datalog: Size,
+    signature: Size,
+    next_key: Size,
+    /// Signature and public key of a third-party block
+    external_signature: Option<Size>,
+    /// Symbols added to the symbol table by this block
+    symbols_count: usize,
+    symbols: Size,
+    largest_symbols: Vec<SymbolSize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TokenSize {
+    total: Size,
+    blocks: Vec<BlockSize>,
+    proof: Size,
+}
+
+/// Breaks down the size of a serialized token, from its protobuf structure
+pub fn token_size(bytes: &[u8]) -> Result<TokenSize> {
+    let token = schema::Biscuit::decode(bytes)
+        .map_err(|e| ParseError("biscuit".to_string(), e.to_string()))?;
+
+    let blocks = std::iter::once(&token.authority)
+        .chain(token.blocks.iter())
+        .map(block_size)
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(TokenSize {
+        total: Size::new(bytes.len()),
+        blocks,
+        proof: Size::new(token.proof.encoded_len()),
+    })
+}
+
+fn block_size(signed_block: &schema::SignedBlock) -> Result<BlockSize> {
+    let block = schema::Block::decode(&signed_block.block[..])
+        .map_err(|e| ParseError("block".to_string(), e.to_string()))?;
+
+    let mut largest_symbols = block
+        .symbols
+        .iter()
+        .map(|s| SymbolSize {
+            symbol: s.clone(),
+            bytes: s.len(),
+        })
+        .collect::<Vec<_>>();
+    largest_symbols.sort_by_key(|s| std::cmp::Reverse(s.bytes));
+    largest_symbols.truncate(LARGEST_SYMBOLS);
+
+    Ok(BlockSize {
+        total: Size::new(signed_block.encoded_len()),
+        datalog: Size::new(signed_block.block.len()),
+        signature: Size::new(signed_block.signature.len()),
+        next_key: Size::new(signed_block.next_key.key.len()),
+        external_signature: signed_block
+            .external_signature
+            .as_ref()
+            .map(|e| Size::new(e.signature.len() + e.public_key.key.len())),
+        symbols_count: block.symbols.len(),
+        symbols: Size::new(block.symbols.iter().map(|s| s.len()).sum()),
+        largest_symbols,
+    })
+}
+
+impl Display for BlockSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n  datalog: {}, signature: {}, next key: {}",
+            self.total, self.datalog, self.signature, self.next_key
+        )?;
+        if let Some(external_signature) = self.external_signature {
+            write!(f, ", external signature: {}", external_signature)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  symbols: {}, {}", self.symbols_count, self.symbols)?;
+        for s in &self.largest_symbols {
+            let mut symbol = s.symbol.chars().take(40).collect::<String>();
+            if symbol.len() < s.symbol.len() {
+                symbol.push('…');
+            }
+            writeln!(f, "  - {:?} ({} bytes)", symbol, s.bytes)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for TokenSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "== Size ==")?;
+        writeln!(f, "Total: {}", self.total)?;
+        for (i, block) in self.blocks.iter().enumerate() {
+            if i == 0 {
+                write!(f, "Authority block: {}", block)?;
+            } else {
+                write!(f, "Block n°{}: {}", i, block)?;
+            }
+        }
+        writeln!(f, "Proof: {}", self.proof)?;
+        writeln!(f, "\n==========\n")
+    }
+}
+
+#[test]
+fn known_token_size() {
+    use biscuit_auth::{Biscuit, KeyPair};
+
+    // ed25519 keys and signatures have a fixed size, so the token size is known
+    let token = Biscuit::builder()
+        .fact("right(\"file1\")")
+        .unwrap()
+        .build(&KeyPair::new())
+        .unwrap()
+        .to_vec()
+        .unwrap();
+    let size = token_size(&token).unwrap();
+    assert_eq!(
+        size.total,
+        Size {
+            raw: 164,
+            base64: 220
+        }
+    );
+    assert_eq!(size.blocks.len(), 1);
+    let block = &size.blocks[0];
+    assert_eq!(
+        block.total,
+        Size {
+            raw: 126,
+            base64: 168
+        }
+    );
+    assert_eq!(
+        block.datalog,
+        Size {
+            raw: 20,
+            base64: 28
+        }
+    );
+    assert_eq!(
+        block.signature,
+        Size {
+            raw: 64,
+            base64: 88
+        }
+    );
+    assert_eq!(
+        block.next_key,
+        Size {
+            raw: 32,
+            base64: 44
+        }
+    );
+    assert_eq!(block.symbols, Size { raw: 5, base64: 8 });
+    assert_eq!(
+        size.proof,
+        Size {
+            raw: 34,
+            base64: 48
+        }
+    );
+}
@@ -0,0 +1,283 @@
+/*
+ * SPDX-FileCopyrightText: 2021 Clément Delafargue <clement@delafargue.name>
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+use anyhow::{bail, Result};
+use biscuit_auth::builder::Term;
+use serde::Deserialize;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+
+use crate::errors::CliError::*;
+use crate::input::*;
+
+const PARAM_TYPES: [&str; 6] = ["string", "integer", "date", "bytes", "bool", "pubkey"];
+
+/// A token template, read from a TOML file: the authority block datalog, along with
+/// the parameters it expects and default values for the token settings
+///
+/// ```toml
+/// description = "Read-only access to a user's documents"
+/// ttl = "1d"
+/// root_key_id = 1
+/// authority = """
+/// user({user});
+/// operations({operations});
+/// """
+///
+/// [[params]]
+/// name = "user"
+/// description = "The user id"
+///
+/// [[params]]
+/// name = "operations"
+/// type = "set<string>"
+/// default = ["read"]
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    pub description: Option<String>,
+    pub authority: String,
+    pub context: Option<String>,
+    pub ttl: Option<String>,
+    pub root_key_id: Option<u32>,
+    #[serde(default)]
+    pub params: Vec<TemplateParam>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateParam {
+    pub name: String,
+    /// Same types as `--param`: pubkey, string, integer, date, bytes, bool, set or set<type>
+    #[serde(rename = "type", default = "default_param_type")]
+    pub param_type: String,
+    pub default: Option<toml::Value>,
+    pub description: Option<String>,
+}
+
+fn default_param_type() -> String {
+    "string".to_string()
+}
+
+/// Reads a template file and makes sure its parameter types, default values and TTL are valid
+pub fn read_template(path: &Path) -> Result<Template> {
+    let contents = fs::read_to_string(path).map_err(|_| FileNotFound(path.to_path_buf()))?;
+    let template: Template =
+        toml::from_str(&contents).map_err(|e| ParseError("template".to_string(), e.to_string()))?;
+
+    for (i, param) in template.params.iter().enumerate() {
+        if !is_param_type(&param.param_type) {
+            bail!(
+                "Unsupported type `{}` for template parameter `{}`",
+                param.param_type,
+                param.name
+            );
+        }
+        if template.params[..i].iter().any(|p| p.name == param.name) {
+            bail!("Template parameter `{}` is declared twice", param.name);
+        }
+        param.default_value()?;
+    }
+    template.parsed_ttl()?;
+
+    Ok(template)
+}
+
+fn is_param_type(param_type: &str) -> bool {
+    match set_element_annotation(param_type) {
+        Some(None) => true,
+        Some(Some(element)) => element != "pubkey" && PARAM_TYPES.contains(&element),
+        None => PARAM_TYPES.contains(&param_type),
+    }
+}
+
+impl Template {
+    pub fn parsed_ttl(&self) -> Result<Option<Ttl>> {
+        self.ttl
+            .as_deref()
+            .map(|ttl| {
+                parse_ttl(ttl).map_err(|e| ParseError("template ttl".to_string(), e.to_string()))
+            })
+            .transpose()
+            .map_err(|e| e.into())
+    }
+
+    /// Checks the provided parameters against the template declarations, and
+    /// fills the missing ones with their default values.
+    ///
+    /// Untyped values (strings) are parsed according to the declared type, so
+    /// that `--param age=42` can be used for an `integer` parameter.
+    pub fn params(&self, provided: &[Param]) -> Result<Vec<Param>> {
+        for p in provided {
+            if !self.params.iter().any(|d| &d.name == param_name(p)) {
+                bail!(
+                    "Parameter `{}` is not declared by the template",
+                    param_name(p)
+                );
+            }
+        }
+
+        let mut missing = vec![];
+        let mut params = vec![];
+        for declared in &self.params {
+            // later values take precedence, as with `--param` and `--params-file`
+            match provided
+                .iter()
+                .rev()
+                .find(|p| param_name(p) == &declared.name)
+            {
+                Some(p) => params.push(declared.check_value(p)?),
+                None => match declared.default_value()? {
+                    Some(p) => params.push(p),
+                    None => missing.push(format!("{} ({})", declared.name, declared.param_type)),
+                },
+            }
+        }
+        if !missing.is_empty() {
+            Err(MissingParams(missing.join(", ")))?
+        }
+
+        Ok(params)
+    }
+}
+
+impl TemplateParam {
+    fn default_value(&self) -> Result<Option<Param>> {
+        self.default
+            .clone()
+            .map(|value| {
+                param_from_toml(&self.name, Some(&self.param_type), value).map_err(|e| {
+                    ParseError(format!("default value for `{}`", self.name), e.to_string()).into()
+                })
+            })
+            .transpose()
+    }
+
+    fn check_value(&self, param: &Param) -> Result<Param> {
+        if let (Param::Term(_, Term::Str(s)), false) = (param, self.param_type == "string") {
+            return parse_param_value(&self.name, Some(&self.param_type), s)
+                .map_err(|e| ParseError(format!("param `{}`", self.name), e.to_string()).into());
+        }
+
+        let matches = match (param, set_element_annotation(&self.param_type)) {
+            (Param::PublicKey(_, _), _) => self.param_type == "pubkey",
+            (Param::Term(_, Term::Set(elements)), Some(element)) => {
+                element.map_or(true, |e| elements.iter().all(|t| term_type(t) == e))
+            }
+            (Param::Term(_, t), _) => term_type(t) == self.param_type,
+        };
+        if !matches {
+            bail!(
+                "Parameter `{}` expects a value of type `{}`",
+                self.name,
+                self.param_type
+            );
+        }
+        Ok(param.clone())
+    }
+}
+
+fn param_name(param: &Param) -> &String {
+    match param {
+        Param::Term(name, _) | Param::PublicKey(name, _) => name,
+    }
+}
+
+impl Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(description) = &self.description {
+            writeln!(f, "{}\n", description)?;
+        }
+        if let Some(root_key_id) = &self.root_key_id {
+            writeln!(f, "Root key id: {}", root_key_id)?;
+        }
+        if let Some(ttl) = &self.ttl {
+            writeln!(f, "TTL: {}", ttl)?;
+        }
+        if let Some(context) = &self.context {
+            writeln!(f, "Context: {}", context)?;
+        }
+
+        if self.params.is_empty() {
+            writeln!(f, "Parameters: none")?;
+        } else {
+            writeln!(f, "Parameters:")?;
+            for param in &self.params {
+                write!(f, "  {} ({}", param.name, param.param_type)?;
+                match &param.default {
+                    Some(default) => write!(f, ", default: {})", default)?,
+                    None => write!(f, ", required)")?,
+                }
+                match &param.description {
+                    Some(description) => writeln!(f, ": {}", description)?,
+                    None => writeln!(f)?,
+                }
+            }
+        }
+
+        writeln!(f, "Authority block:")?;
+        write!(f, "{}", self.authority.trim_end())
+    }
+}
+
+#[test]
+fn template_params() {
+    let template: Template = toml::from_str(
+        r#"
+        authority = "user({user}); age({age}); operations({operations});"
+
+        [[params]]
+        name = "user"
+
+        [[params]]
+        name = "age"
+        type = "integer"
+
+        [[params]]
+        name = "operations"
+        type = "set<string>"
+        default = ["read"]
+        "#,
+    )
+    .unwrap();
+
+    let params = template
+        .params(&[
+            parse_param("age=42").unwrap(),
+            parse_param("user=alice").unwrap(),
+        ])
+        .unwrap();
+    assert_eq!(
+        params
+            .iter()
+            .map(|p| format!("{:?}", p))
+            .collect::<Vec<_>>(),
+        vec![
+            r#"Term("user", Str("alice"))"#,
+            r#"Term("age", Integer(42))"#,
+            r#"Term("operations", Set({Str("read")}))"#,
+        ]
+    );
+
+    assert_eq!(
+        template.params(&[]).unwrap_err().to_string(),
+        "Missing datalog parameters: user (string), age (integer)"
+    );
+    assert!(template
+        .params(&[
+            parse_param("age:bool=true").unwrap(),
+            parse_param("user=alice").unwrap()
+        ])
+        .is_err());
+    assert!(template
+        .params(&[
+            parse_param("age=42").unwrap(),
+            parse_param("user=alice").unwrap(),
+            parse_param("other=value").unwrap()
+        ])
+        .is_err());
+}
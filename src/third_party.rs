@@ -0,0 +1,189 @@
+/*
+ * SPDX-FileCopyrightText: 2021 Clément Delafargue <clement@delafargue.name>
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+use anyhow::Result;
+use biscuit_auth::{
+    format::{convert::proto_block_to_token_block, schema},
+    PublicKey, ThirdPartyRequest,
+};
+use prost::Message;
+use std::convert::TryFrom;
+
+use crate::errors::CliError::*;
+use crate::input::*;
+
+/// Version of the external signature payload generated by biscuit-auth
+const EXTERNAL_SIGNATURE_VERSION: u32 = 1;
+
+/// A decoded third-party block, as generated by `generate-third-party-block`
+pub struct ThirdPartyBlockContents {
+    /// The serialized block, as accepted by `append_third_party`
+    pub bytes: Vec<u8>,
+    pub external_key: PublicKey,
+    pub version: u32,
+    pub context: Option<String>,
+    pub code: BlockCode,
+    payload: Vec<u8>,
+    external_signature: Vec<u8>,
+}
+
+impl ThirdPartyBlockContents {
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let parse_error =
+            |e: prost::DecodeError| ParseError("third-party block".to_string(), e.to_string());
+        let contents = schema::ThirdPartyBlockContents::decode(bytes).map_err(parse_error)?;
+        let block = schema::Block::decode(&contents.payload[..]).map_err(parse_error)?;
+        let external_key = proto_public_key(&contents.external_signature.public_key)?;
+
+        // third-party blocks have their own symbol table and public keys
+        let token_block = proto_block_to_token_block(&block, Some(external_key))?;
+        let symbols = &token_block.symbols;
+        let source = token_block
+            .facts
+            .iter()
+            .map(|f| symbols.print_fact(f))
+            .chain(token_block.rules.iter().map(|r| symbols.print_rule(r)))
+            .chain(token_block.checks.iter().map(|c| symbols.print_check(c)))
+            .map(|statement| format!("{};\n", statement))
+            .collect::<String>();
+        let mut code = BlockCode::parse(&source)?;
+        let keys = block
+            .public_keys
+            .iter()
+            .map(proto_public_key)
+            .collect::<Result<Vec<_>>>()?;
+        code.scopes = proto_scopes(&block.scope, &keys)?;
+
+        Ok(ThirdPartyBlockContents {
+            bytes: bytes.to_vec(),
+            external_key,
+            version: token_block.version,
+            context: token_block.context,
+            code,
+            payload: contents.payload,
+            external_signature: contents.external_signature.signature,
+        })
+    }
+
+    /// Checks the external signature against the request the block answers. Blocks
+    /// sign the signature of the previous block, legacy blocks signed its public key
+    pub fn check_request(
+        &self,
+        request: &ThirdPartyRequest,
+    ) -> Result<std::result::Result<(), String>> {
+        let mut signed = b"\0EXTERNAL\0\0VERSION\0".to_vec();
+        signed.extend(EXTERNAL_SIGNATURE_VERSION.to_le_bytes());
+        signed.extend(b"\0PAYLOAD\0");
+        signed.extend(&self.payload);
+        signed.extend(b"\0PREVSIG\0");
+        signed.extend(request_previous_signature(request)?);
+
+        let result = verify_signature(&self.external_key, &signed, &self.external_signature);
+        match (result, request_previous_key(request)?) {
+            (Err(_), Some(previous_key)) => {
+                let mut signed = self.payload.clone();
+                signed.extend((previous_key.algorithm() as i32).to_le_bytes());
+                signed.extend(previous_key.to_bytes());
+                Ok(verify_signature(
+                    &self.external_key,
+                    &signed,
+                    &self.external_signature,
+                ))
+            }
+            (result, _) => Ok(result),
+        }
+    }
+}
+
+fn verify_signature(
+    key: &PublicKey,
+    data: &[u8],
+    signature: &[u8],
+) -> std::result::Result<(), String> {
+    match key.algorithm() {
+        schema::public_key::Algorithm::Ed25519 => {
+            let key = ed25519_dalek::VerifyingKey::try_from(&key.to_bytes()[..])
+                .map_err(|e| e.to_string())?;
+            let signature =
+                ed25519_dalek::Signature::from_slice(signature).map_err(|e| e.to_string())?;
+            key.verify_strict(data, &signature)
+                .map_err(|e| e.to_string())
+        }
+        schema::public_key::Algorithm::Secp256r1 => {
+            use p256::ecdsa::signature::Verifier;
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(&key.to_bytes())
+                .map_err(|e| e.to_string())?;
+            let signature =
+                p256::ecdsa::Signature::from_der(signature).map_err(|e| e.to_string())?;
+            key.verify(data, &signature).map_err(|e| e.to_string())
+        }
+    }
+}
+
+pub fn read_third_party_block_from(from: &BiscuitBytes) -> Result<ThirdPartyBlockContents> {
+    let bytes = read_bytes_from(from, "base64-encoded third-party block")?;
+    ThirdPartyBlockContents::decode(&bytes)
+}
+
+#[test]
+fn third_party_block_contents() {
+    use biscuit_auth::{Biscuit, BlockBuilder, KeyPair};
+
+    let root = KeyPair::new();
+    let external = KeyPair::new();
+    let biscuit = Biscuit::builder()
+        .fact("user(\"1234\")")
+        .unwrap()
+        .build(&root)
+        .unwrap();
+    let request = biscuit.third_party_request().unwrap();
+    let block = request
+        .create_block(
+            &external.private(),
+            BlockBuilder::new().fact("group(\"admin\")").unwrap(),
+        )
+        .unwrap();
+
+    let bytes = block.serialize().unwrap();
+    let contents = ThirdPartyBlockContents::decode(&bytes).unwrap();
+    assert_eq!(contents.external_key, external.public());
+    assert_eq!(contents.code.to_string(), "group(\"admin\");\n");
+    assert_eq!(contents.bytes, bytes);
+}
+
+#[test]
+fn third_party_block_request_signatures() {
+    use biscuit_auth::{Biscuit, BlockBuilder, KeyPair};
+
+    let root = KeyPair::new();
+    let biscuit = Biscuit::builder()
+        .fact("user(\"1234\")")
+        .unwrap()
+        .build(&root)
+        .unwrap();
+    let request = biscuit.third_party_request().unwrap();
+    let other_request = biscuit
+        .append(BlockBuilder::new())
+        .unwrap()
+        .third_party_request()
+        .unwrap();
+
+    for external in [
+        KeyPair::new(),
+        KeyPair::new_with_algorithm(biscuit_auth::Algorithm::Secp256r1),
+    ] {
+        let block = biscuit
+            .third_party_request()
+            .unwrap()
+            .create_block(
+                &external.private(),
+                BlockBuilder::new().fact("group(\"admin\")").unwrap(),
+            )
+            .unwrap();
+        let contents = ThirdPartyBlockContents::decode(&block.serialize().unwrap()).unwrap();
+        assert_eq!(contents.check_request(&request).unwrap(), Ok(()));
+        assert!(contents.check_request(&other_request).unwrap().is_err());
+    }
+}